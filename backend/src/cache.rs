@@ -0,0 +1,242 @@
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use crate::{decoder::CachedFrame, ffmpeg::filter::FrameSize};
+
+/// Decoded frames of every clip, in one memory budget.
+pub static FRAME_CACHE: LazyLock<FrameCache> = LazyLock::new(FrameCache::new);
+
+const DEFAULT_MAX_SIZE: usize = 1024 * 1024 * 1024 * 4; // Default: 4GiB
+
+pub fn set_max_cache_size(bytes: usize) {
+    FRAME_CACHE.set_max_size(bytes.max(1024 * 1024));
+}
+
+pub fn get_cache_usage() -> (usize, usize) {
+    FRAME_CACHE.usage()
+}
+
+/// Identifies the clip (`DecoderKey`) a frame belongs to. Assigned per `CachedDecoder`.
+pub type ClipId = u64;
+
+/// A frame of a clip: as decoded (`None`), or scaled to a requested size.
+type FrameKey = (ClipId, u32, Option<FrameSize>);
+
+pub struct FrameCache {
+    state: Mutex<CacheState>,
+}
+
+#[derive(Default)]
+struct CacheState {
+    frames: HashMap<FrameKey, CacheEntry>,
+    /// Last frame requested for display, per clip.
+    playheads: HashMap<ClipId, u32>,
+    size: usize,
+    max_size: usize,
+    /// Incremented on every insert and hit. `CacheEntry::last_used` is a reading of it.
+    clock: u64,
+}
+
+struct CacheEntry {
+    frame: CachedFrame,
+    last_used: u64,
+}
+
+impl CacheState {
+    /// How much an entry deserves eviction: accesses since it was last used plus its
+    /// distance in frames from its clip's playhead. Frames near any playhead stay cached
+    /// even when old, so scrubbing back and forth keeps hitting.
+    fn eviction_cost(&self, (clip, frame, _): FrameKey, entry: &CacheEntry) -> u64 {
+        let age = self.clock - entry.last_used;
+        let distance = match self.playheads.get(&clip) {
+            Some(playhead) => playhead.abs_diff(frame) as u64,
+            None => u32::MAX as u64,
+        };
+        age.saturating_add(distance)
+    }
+
+    /// Evict until `incoming` more bytes fit in `max_size`. Goes down to 90% of the limit so
+    /// that a full cache does not rank every frame on every insert.
+    fn make_room(&mut self, incoming: usize) {
+        if self.size + incoming <= self.max_size {
+            return;
+        }
+
+        let target = (self.max_size / 10 * 9).saturating_sub(incoming);
+
+        let mut candidates = self
+            .frames
+            .iter()
+            .map(|(key, entry)| (self.eviction_cost(*key, entry), *key))
+            .collect::<Vec<_>>();
+        candidates.sort_unstable_by_key(|(cost, _)| Reverse(*cost));
+
+        for (_, key) in candidates {
+            if self.size <= target {
+                break;
+            }
+            if let Some(entry) = self.frames.remove(&key) {
+                self.size -= entry.frame.rgba.len();
+            }
+        }
+    }
+}
+
+impl FrameCache {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(CacheState {
+                max_size: DEFAULT_MAX_SIZE,
+                ..Default::default()
+            }),
+        }
+    }
+
+    pub(crate) fn get(&self, clip: ClipId, frame: u32) -> Option<CachedFrame> {
+        self.get_key((clip, frame, None))
+    }
+
+    /// `frame` of `clip` as already scaled to `size`.
+    pub(crate) fn get_resized(
+        &self,
+        clip: ClipId,
+        frame: u32,
+        size: FrameSize,
+    ) -> Option<CachedFrame> {
+        self.get_key((clip, frame, Some(size)))
+    }
+
+    fn get_key(&self, key: FrameKey) -> Option<CachedFrame> {
+        let mut state = self.state.lock().unwrap();
+        state.clock += 1;
+        let clock = state.clock;
+        let entry = state.frames.get_mut(&key)?;
+        entry.last_used = clock;
+        Some(entry.frame.clone())
+    }
+
+    pub fn contains(&self, clip: ClipId, frame: u32) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .frames
+            .contains_key(&(clip, frame, None))
+    }
+
+    /// Store a frame, evicting others first so the total never exceeds the limit.
+    /// A frame larger than the whole cache is not stored.
+    pub(crate) fn insert(&self, clip: ClipId, frame: u32, cached: CachedFrame) {
+        self.insert_key((clip, frame, None), cached);
+    }
+
+    /// Store `frame` of `clip` scaled to `size`, so the next request at that size doesn't
+    /// scale it again. Shares the budget with the decoded frames.
+    pub(crate) fn insert_resized(
+        &self,
+        clip: ClipId,
+        frame: u32,
+        size: FrameSize,
+        cached: CachedFrame,
+    ) {
+        self.insert_key((clip, frame, Some(size)), cached);
+    }
+
+    fn insert_key(&self, key: FrameKey, cached: CachedFrame) {
+        let mut state = self.state.lock().unwrap();
+        let len = cached.rgba.len();
+        if len > state.max_size {
+            return;
+        }
+
+        if let Some(old) = state.frames.remove(&key) {
+            state.size -= old.frame.rgba.len();
+        }
+        state.make_room(len);
+
+        state.clock += 1;
+        let last_used = state.clock;
+        state.size += len;
+        state.frames.insert(
+            key,
+            CacheEntry {
+                frame: cached,
+                last_used,
+            },
+        );
+    }
+
+    pub fn set_playhead(&self, clip: ClipId, frame: u32) {
+        self.state.lock().unwrap().playheads.insert(clip, frame);
+    }
+
+    /// Drop every frame of `clip`.
+    pub fn remove_clip(&self, clip: ClipId) {
+        let mut state = self.state.lock().unwrap();
+        let mut removed = 0;
+        state.frames.retain(|(frame_clip, _, _), entry| {
+            let keep = *frame_clip != clip;
+            if !keep {
+                removed += entry.frame.rgba.len();
+            }
+            keep
+        });
+        state.size -= removed;
+        state.playheads.remove(&clip);
+    }
+
+    pub fn set_max_size(&self, bytes: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.max_size = bytes;
+        state.make_room(0);
+    }
+
+    /// Returns `(used bytes, max bytes)`.
+    pub fn usage(&self) -> (usize, usize) {
+        let state = self.state.lock().unwrap();
+        (state.size, state.max_size)
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.frames.clear();
+        state.playheads.clear();
+        state.size = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::protocol::FrameFlags;
+
+    fn frame(len: usize) -> CachedFrame {
+        CachedFrame {
+            rgba: Arc::new(vec![0; len]),
+            source_frame: 0,
+            flags: FrameFlags::NONE,
+        }
+    }
+
+    #[test]
+    fn full_cache_evicts_far_frames_down_to_the_low_water_mark() {
+        let cache = FrameCache::new();
+        cache.set_max_size(1000);
+        cache.set_playhead(1, 0);
+        // 再生位置から遠く、古いフレームほど先に捨てられる
+        for index in (0..10).rev() {
+            cache.insert(1, index, frame(100));
+        }
+        assert_eq!(cache.usage().0, 1000);
+
+        // 1枚分ではなく、上限の 90% から入れる分を引いたところまで空ける
+        cache.insert(1, 10, frame(100));
+        assert_eq!(cache.usage().0, 900);
+        assert!(cache.contains(1, 0) && cache.contains(1, 7) && cache.contains(1, 10));
+        assert!(!cache.contains(1, 8) && !cache.contains(1, 9));
+    }
+}
@@ -1,50 +1,355 @@
 use std::{
-    collections::{HashMap, HashSet},
+    collections::{BinaryHeap, HashMap, HashSet},
     sync::{
-        Arc, LazyLock, Mutex, RwLock,
-        atomic::{AtomicUsize, Ordering},
+        Arc, LazyLock, Mutex, OnceLock, RwLock,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
     },
-    time::Duration,
+    time::{Duration, Instant},
 };
 
-use tokio::time::timeout;
-
-use crate::{ffmpeg::hw_decoder, future::SharedManualFuture};
+use serde::Serialize;
+use tokio::{sync::Notify, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error};
+
+use crate::{
+    cache::{ClipId, FRAME_CACHE},
+    disk_cache::disk_cache,
+    error::DecodeError,
+    ffmpeg::{
+        filter::{FrameRate, FrameSize, OutputParams},
+        stream::StreamSelector,
+    },
+    future::SharedManualFuture,
+    protocol::{FrameFlags, TIMESTAMP_UNKNOWN},
+    remap::{ConformMode, blend_frames},
+    resize::resize_frame,
+    scheduler::{DecodePriority, JobPriority, SCHEDULER, SchedulerStats},
+    source::{FrameSource, SequenceOptions, SourceKind},
+};
 
-pub static DECODER: LazyLock<Decoder> = LazyLock::new(|| Decoder::new());
+pub static DECODER: LazyLock<Decoder> = LazyLock::new(Decoder::new);
 
 pub struct Decoder {
     map: Mutex<HashMap<DecoderKey, CachedDecoder>>,
+    /// Keyed without a size (see `OutputParams::split_size`).
+    clip_sizes: Mutex<HashMap<DecoderKey, ClipSize>>,
+    prefetch_queue: Mutex<BinaryHeap<PrefetchJob>>,
+    prefetch_notify: Notify,
+    prefetch_seq: AtomicU64,
+    prefetch_worker: OnceLock<JoinHandle<()>>,
+    reaper: OnceLock<JoinHandle<()>>,
+}
+
+/// A decoder not used for this long is closed and its frames are dropped from the cache.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+const REAP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Snapshot for `GET /decoder_stats`.
+#[derive(Debug, Serialize)]
+pub struct DecoderStats {
+    pub decoders: usize,
+    pub decode_tasks: usize,
+    pub prefetch_jobs: usize,
+    pub cache_bytes: usize,
+    pub max_cache_bytes: usize,
+    /// `None` when the disk cache is disabled.
+    pub disk_cache_bytes: Option<u64>,
+    pub max_disk_cache_bytes: Option<u64>,
+    pub scheduler: SchedulerStats,
+}
+
+/// Sizes of one clip: its canonical size, and the size it is decoded at.
+#[derive(Debug, Clone, Copy)]
+struct ClipSize {
+    source: (u32, u32),
+    decoded: (u32, u32),
+}
+
+/// A pending prefetch hint. Higher `priority` runs first; equal priorities run in arrival order.
+struct PrefetchJob {
+    priority: u8,
+    seq: u64,
+    decoder: CachedDecoder,
+    start: u32,
+    end: u32,
+    /// Set when `start..=end` are project frames at this rate.
+    project_fps: Option<f64>,
+}
+
+impl PartialEq for PrefetchJob {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for PrefetchJob {}
+
+impl PartialOrd for PrefetchJob {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PrefetchJob {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.seq.cmp(&self.seq))
+    }
 }
 
 impl Decoder {
     fn new() -> Self {
         Self {
             map: Mutex::new(HashMap::new()),
+            clip_sizes: Mutex::new(HashMap::new()),
+            prefetch_queue: Mutex::new(BinaryHeap::new()),
+            prefetch_notify: Notify::new(),
+            prefetch_seq: AtomicU64::new(0),
+            prefetch_worker: OnceLock::new(),
+            reaper: OnceLock::new(),
+        }
+    }
+
+    /// Queue frames `start..=end` of `key` for background decoding, in place of any hint still
+    /// queued for the same clip. With `project_fps` they are project frames, conformed to the
+    /// source's frame rate with `conform`.
+    pub async fn prefetch(
+        &'static self,
+        key: DecoderKey,
+        start: u32,
+        end: u32,
+        project_fps: Option<f64>,
+        conform: ConformMode,
+        priority: u8,
+    ) {
+        if end < start {
+            return;
+        }
+
+        let decoder = match project_fps {
+            Some(fps) => match self.conformed_decoder(key, fps, conform).await {
+                Ok(decoder) => decoder,
+                Err(_) => return,
+            },
+            None => self.cached_decoder(key).await,
+        };
+        let seq = self.prefetch_seq.fetch_add(1, Ordering::Relaxed);
+        let mut queue = self.prefetch_queue.lock().unwrap();
+        // 同じクリップへの古いヒントは、再生位置が変わって要らなくなっている
+        queue.retain(|queued| !Arc::ptr_eq(&queued.decoder.inner, &decoder.inner));
+        queue.push(PrefetchJob {
+            priority,
+            seq,
+            decoder,
+            start,
+            end,
+            project_fps,
+        });
+        drop(queue);
+
+        self.prefetch_worker
+            .get_or_init(|| tokio::spawn(self.run_prefetch()));
+        self.prefetch_notify.notify_one();
+    }
+
+    /// Run queued prefetch jobs one window at a time, so a hint with a higher priority waits
+    /// for at most one window. The rest of a job goes back into the queue, unless a newer hint
+    /// for the same clip arrived meanwhile.
+    async fn run_prefetch(&'static self) {
+        loop {
+            let job = self.prefetch_queue.lock().unwrap().pop();
+            let Some(job) = job else {
+                self.prefetch_notify.notified().await;
+                continue;
+            };
+            let Some(start) = job
+                .decoder
+                .prefetch_window(job.start, job.end, job.project_fps)
+                .await
+            else {
+                continue;
+            };
+
+            let mut queue = self.prefetch_queue.lock().unwrap();
+            if !queue
+                .iter()
+                .any(|queued| Arc::ptr_eq(&queued.decoder.inner, &job.decoder.inner))
+            {
+                queue.push(PrefetchJob { start, ..job });
+            }
         }
     }
 
-    pub async fn cached_decoder(&self, key: DecoderKey) -> CachedDecoder {
-        let mut generated = false;
+    /// The decoder for `key`'s clip, giving frames at `key`'s size. Every size of a clip shares
+    /// one decoder and its cached frames, decoded at the smallest size all of them are scaled
+    /// down from. A size that doesn't fit in it moves the clip to a decoder at a larger size;
+    /// the old one is closed once idle.
+    pub async fn cached_decoder(&'static self, key: DecoderKey) -> CachedDecoder {
+        self.reaper.get_or_init(|| tokio::spawn(self.run_reaper()));
+
+        let (output, size) = key.output.split_size();
+        let key = DecoderKey { output, ..key };
+        let decoded = self.decode_size(&key, size).await;
+        self.decoder_at(key.sized(decoded), size)
+    }
+
+    /// The size `key`'s clip is decoded at once it also gives frames at `size`. Probes the
+    /// source's own size the first time; if that fails the source is left at its canonical
+    /// size, to report the error when it is opened again.
+    async fn decode_size(&self, key: &DecoderKey, size: FrameSize) -> (u32, u32) {
+        let known = self.clip_sizes.lock().unwrap().get(key).copied();
+        let clip = match known {
+            Some(clip) => clip,
+            None => {
+                let probe = key.clone();
+                let source_size = tokio::task::spawn_blocking(move || {
+                    let source = probe.source.open(
+                        &probe.path,
+                        &probe.output,
+                        &probe.sequence,
+                        &probe.stream,
+                    )?;
+                    let metadata = source.metadata();
+                    source.close();
+                    Ok::<_, DecodeError>((metadata.width, metadata.height))
+                })
+                .await;
+                match source_size {
+                    Ok(Ok(source)) => ClipSize {
+                        source,
+                        decoded: (0, 0),
+                    },
+                    _ => return (0, 0),
+                }
+            }
+        };
+
+        let needed = size.covering(clip.source);
+        let decoded = (clip.decoded.0.max(needed.0), clip.decoded.1.max(needed.1));
+        self.clip_sizes
+            .lock()
+            .unwrap()
+            .insert(key.clone(), ClipSize { decoded, ..clip });
+        decoded
+    }
+
+    fn decoder_at(&self, key: DecoderKey, size: FrameSize) -> CachedDecoder {
         let decoder = self
             .map
             .lock()
             .unwrap()
             .entry(key.clone())
-            .or_insert_with(|| {
-                generated = true;
-                CachedDecoder::new(key)
-            })
-            .clone();
+            .or_insert_with(|| CachedDecoder::new(key, size))
+            .with_size(size);
+        decoder.touch();
+        decoder
+    }
+
+    /// The decoder for `key` when its frames are conformed to `project_fps` with `conform`.
+    /// `Nearest` at a lower project rate switches to a decoder that resamples to it, so the
+    /// source frames no project frame shows are dropped right after decoding. `Interpolate`
+    /// switches to a decoder that makes enough frames per source frame.
+    pub async fn conformed_decoder(
+        &'static self,
+        key: DecoderKey,
+        project_fps: f64,
+        conform: ConformMode,
+    ) -> Result<CachedDecoder, DecodeError> {
+        let decoder = self.cached_decoder(key.clone()).await;
+        let source = decoder.open_source().await?;
+        let Some(source_fps) = source.metadata().fps else {
+            return Ok(decoder);
+        };
+
+        let mut key = key;
+        if conform.resamples(source_fps, project_fps) && source.resamples() {
+            key.output.conform_fps = Some(FrameRate(project_fps));
+        } else {
+            let factor = conform.interpolation_factor(source_fps, project_fps);
+            if factor <= key.output.interpolate {
+                return Ok(decoder);
+            }
+            key.output.interpolate = factor;
+        }
+        let decoder = self.cached_decoder(key).await;
+        decoder.open_source().await?;
+        Ok(decoder)
+    }
 
-        if generated {
-            decoder.schedule_gc().await;
+    async fn run_reaper(&'static self) {
+        loop {
+            tokio::time::sleep(REAP_INTERVAL).await;
+            self.evict_idle();
         }
+    }
 
-        decoder
+    /// Close decoders that have not been used for `IDLE_TIMEOUT` and have nothing running or
+    /// queued for prefetch.
+    fn evict_idle(&self) {
+        let queued = self
+            .prefetch_queue
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|job| Arc::as_ptr(&job.decoder.inner))
+            .collect::<HashSet<_>>();
+
+        let mut evicted = Vec::new();
+        let mut map = self.map.lock().unwrap();
+        map.retain(|_, decoder| {
+            let idle = decoder.inner.last_used.lock().unwrap().elapsed() >= IDLE_TIMEOUT;
+            let running = decoder.inner.running_decode_tasks.load(Ordering::Relaxed) > 0
+                || queued.contains(&Arc::as_ptr(&decoder.inner));
+            if idle && !running {
+                evicted.push(decoder.clone());
+                false
+            } else {
+                true
+            }
+        });
+        // デコーダが残っていないクリップは、次に使われたときにサイズを測り直す
+        self.clip_sizes
+            .lock()
+            .unwrap()
+            .retain(|key, clip| map.contains_key(&key.sized(clip.decoded)));
+        drop(map);
+
+        for decoder in evicted {
+            debug!(path = decoder.inner.path, "closing idle decoder");
+            decoder.close();
+        }
+    }
+
+    pub fn stats(&self) -> DecoderStats {
+        let (decoders, decode_tasks) = {
+            let map = self.map.lock().unwrap();
+            let decode_tasks = map
+                .values()
+                .map(|decoder| decoder.inner.running_decode_tasks.load(Ordering::Relaxed))
+                .sum();
+            (map.len(), decode_tasks)
+        };
+        let (cache_bytes, max_cache_bytes) = FRAME_CACHE.usage();
+        let disk_usage = disk_cache().map(|disk| disk.usage());
+
+        DecoderStats {
+            decoders,
+            decode_tasks,
+            prefetch_jobs: self.prefetch_queue.lock().unwrap().len(),
+            cache_bytes,
+            max_cache_bytes,
+            disk_cache_bytes: disk_usage.map(|(used, _)| used),
+            max_disk_cache_bytes: disk_usage.map(|(_, max)| max),
+            scheduler: SCHEDULER.stats(),
+        }
     }
 
     pub async fn clear(&self) {
+        self.prefetch_queue.lock().unwrap().clear();
+        self.clip_sizes.lock().unwrap().clear();
+
         let map_clone = {
             let mut map = self.map.lock().unwrap();
 
@@ -54,6 +359,10 @@ impl Decoder {
             temp
         };
 
+        for decoder in map_clone.values() {
+            decoder.close();
+        }
+
         loop {
             // await decode task
             let mut finished = true;
@@ -71,282 +380,656 @@ impl Decoder {
             tokio::time::sleep(Duration::from_millis(50)).await;
         }
 
-        ENTIRE_CACHE_SIZE.store(0, Ordering::Relaxed);
+        FRAME_CACHE.clear();
     }
 }
 
-static ENTIRE_CACHE_SIZE: AtomicUsize = AtomicUsize::new(0);
-static MAX_CACHE_SIZE: AtomicUsize = AtomicUsize::new(1024 * 1024 * 1024 * 4); // Default: 4GiB
+/// Number of frames decoded per window.
+const DECODE_CHUNK: u32 = 120;
 
-pub fn set_max_cache_size(bytes: usize) {
-    MAX_CACHE_SIZE.store(bytes.max(1024 * 1024), Ordering::Relaxed);
-}
-
-pub fn get_cache_usage() -> (usize, usize) {
-    (
-        ENTIRE_CACHE_SIZE.load(Ordering::Relaxed),
-        MAX_CACHE_SIZE.load(Ordering::Relaxed),
-    )
-}
+static NEXT_CLIP_ID: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct DecoderKey {
     pub path: String,
+    pub output: OutputParams,
+    pub source: SourceKind,
+    /// Only used by image sequences.
+    pub sequence: SequenceOptions,
+    /// Video stream of multi-track files.
+    pub stream: StreamSelector,
+}
+
+impl DecoderKey {
+    /// This key with the output set to `width` x `height`.
+    fn sized(&self, (width, height): (u32, u32)) -> DecoderKey {
+        DecoderKey {
+            output: OutputParams {
+                width,
+                height,
+                ..self.output.clone()
+            },
+            ..self.clone()
+        }
+    }
+}
+
+/// A frame as stored in the cache.
+#[derive(Debug, Clone)]
+pub(crate) struct CachedFrame {
+    pub(crate) rgba: Arc<Vec<u8>>,
+    /// Index of the frame whose pixels are in `rgba`.
+    pub(crate) source_frame: u32,
+    pub(crate) flags: FrameFlags,
+}
+
+type FrameResult = Result<CachedFrame, DecodeError>;
+
+/// A frame whose window is being decoded.
+#[derive(Debug, Clone)]
+struct PendingFrame {
+    frame: SharedManualFuture<FrameResult>,
+    /// Priority of the window, raised by callers that need the frame sooner.
+    priority: JobPriority,
+}
+
+/// A frame returned to the caller, with enough information to build a packet header.
+#[derive(Debug, Clone)]
+pub struct DecodedFrame {
+    pub rgba: Arc<Vec<u8>>,
     pub width: u32,
     pub height: u32,
+    /// Index of the frame whose pixels are in `rgba`.
+    pub source_frame: u32,
+    pub timestamp_us: i64,
+    pub flags: FrameFlags,
 }
 
+/// One clip's decoder, giving frames at one output size.
 #[derive(Debug, Clone)]
 pub struct CachedDecoder {
     inner: Arc<Inner>,
+    size: FrameSize,
 }
 
 #[derive(Debug)]
 struct Inner {
+    id: ClipId,
     path: String,
-    width: u32,
-    height: u32,
-    frames: RwLock<HashMap<u32, SharedManualFuture<Vec<u8>>>>,
-    frame_states: RwLock<HashMap<u32, FrameState>>,
-    decoding_frames: Mutex<HashSet<u32>>,
+    /// Sized to cover every size requested of the clip (see `Decoder::cached_decoder`).
+    /// Without a size, frames are decoded at the source's canonical size.
+    output: OutputParams,
+    source_kind: SourceKind,
+    sequence: SequenceOptions,
+    stream: StreamSelector,
+    /// Opened on first use. Only success is remembered, so a file that appears later is
+    /// picked up without a reset.
+    source: Mutex<Option<Arc<dyn FrameSource>>>,
+    /// Frames being decoded. A frame is moved into `FRAME_CACHE` before it leaves this map.
+    pending: RwLock<HashMap<u32, PendingFrame>>,
     running_decode_tasks: AtomicUsize,
-}
-
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
-enum FrameState {
-    None,
-    Wait,
-    Drop,
+    last_used: Mutex<Instant>,
+    /// Cancelled when the decoder is removed, to stop its decode and prefetch work.
+    cancel: CancellationToken,
+    /// Held for a whole window, so concurrent windows don't make the source seek back and forth.
+    /// Taken before the window waits for a scheduler slot, so a window never holds a slot
+    /// while it waits for another window of the same clip.
+    decode_lock: Arc<tokio::sync::Mutex<()>>,
 }
 
 impl CachedDecoder {
-    fn new(key: DecoderKey) -> Self {
+    fn new(key: DecoderKey, size: FrameSize) -> Self {
         let inner = Inner {
+            id: NEXT_CLIP_ID.fetch_add(1, Ordering::Relaxed),
             path: key.path,
-            width: key.width,
-            height: key.height,
-            frames: RwLock::new(HashMap::new()),
-            frame_states: RwLock::new(HashMap::new()),
-            decoding_frames: Mutex::new(HashSet::new()),
+            output: key.output,
+            source_kind: key.source,
+            sequence: key.sequence,
+            stream: key.stream,
+            source: Mutex::new(None),
+            pending: RwLock::new(HashMap::new()),
             running_decode_tasks: AtomicUsize::new(0),
+            last_used: Mutex::new(Instant::now()),
+            cancel: CancellationToken::new(),
+            decode_lock: Arc::new(tokio::sync::Mutex::new(())),
         };
         Self {
             inner: Arc::new(inner),
+            size,
         }
     }
 
-    async fn schedule_gc(&self) {
-        let self_clone = self.clone();
+    fn with_size(&self, size: FrameSize) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            size,
+        }
+    }
 
-        tokio::spawn(async move {
-            loop {
-                if ENTIRE_CACHE_SIZE.load(Ordering::Relaxed)
-                    >= MAX_CACHE_SIZE.load(Ordering::Relaxed)
-                {
-                    let mut frames = self_clone.inner.frames.write().unwrap();
-
-                    let all_frame_index = frames.keys().cloned().collect::<Vec<_>>();
-
-                    for frame_index in all_frame_index.into_iter().rev() {
-                        let future = frames.get(&frame_index).unwrap();
-                        let mut frame_states = self_clone.inner.frame_states.write().unwrap();
-                        let frame_state = frame_states
-                            .get(&frame_index)
-                            .cloned()
-                            .unwrap_or(FrameState::None);
-
-                        if future.is_completed() && frame_state == FrameState::None {
-                            let future = frames.remove(&frame_index).unwrap();
-                            frame_states.insert(frame_index, FrameState::Drop);
-
-                            ENTIRE_CACHE_SIZE
-                                .fetch_sub(future.get_now().unwrap().len(), Ordering::Relaxed);
-
-                            if ENTIRE_CACHE_SIZE.load(Ordering::Relaxed)
-                                < MAX_CACHE_SIZE.load(Ordering::Relaxed)
-                            {
-                                break;
-                            }
-                        }
-                    }
-                }
+    fn touch(&self) {
+        *self.inner.last_used.lock().unwrap() = Instant::now();
+    }
 
-                tokio::time::sleep(Duration::from_secs(5)).await;
-            }
-        });
+    /// Stop in-flight work, close the source and drop this decoder's frames.
+    fn close(&self) {
+        self.inner.cancel.cancel();
+        if let Some(source) = self.inner.source.lock().unwrap().take() {
+            source.close();
+        }
+        FRAME_CACHE.remove_clip(self.inner.id);
     }
 
-    pub async fn get_frame(&self, frame_index: u32) -> Arc<Vec<u8>> {
+    /// Open the source on a blocking thread, so probing the file (ffprobe, building the frame
+    /// index) doesn't stall the runtime. Later `source()` calls return it right away.
+    pub async fn open_source(&self) -> Result<Arc<dyn FrameSource>, DecodeError> {
+        if let Some(source) = self.inner.source.lock().unwrap().as_ref() {
+            return Ok(source.clone());
+        }
+        let decoder = self.clone();
+        tokio::task::spawn_blocking(move || {
+            let source = decoder.source()?;
+            // インデックスとフレームレートもここで読んでおく
+            source.metadata();
+            Ok(source)
+        })
+        .await
+        .map_err(|e| DecodeError::ffmpeg(format!("open task failed: {e}")))?
+    }
+
+    fn source(&self) -> Result<Arc<dyn FrameSource>, DecodeError> {
+        let mut source = self.inner.source.lock().unwrap();
+        if let Some(source) = source.as_ref() {
+            return Ok(source.clone());
+        }
+        let opened = self.inner.source_kind.open(
+            &self.inner.path,
+            &self.inner.output,
+            &self.inner.sequence,
+            &self.inner.stream,
+        )?;
+        Ok(source.insert(opened).clone())
+    }
+
+    fn timestamp_us(&self, frame_index: u32) -> i64 {
+        match self
+            .source()
+            .ok()
+            .and_then(|source| source.frame_time(frame_index))
         {
-            let mut decoding_frames = self.inner.decoding_frames.lock().unwrap();
+            Some(seconds) => (seconds * 1_000_000.0).round() as i64,
+            None => TIMESTAMP_UNKNOWN,
+        }
+    }
 
-            const DECODE_CHUNK: u32 = 120;
+    /// Map a presentation time (seconds from the first frame) to the frame shown at that time.
+    pub fn frame_at_time(&self, seconds: f64) -> Result<u32, DecodeError> {
+        self.source()?.seek(seconds)
+    }
 
-            if !decoding_frames.contains(&frame_index) {
-                let mut last_frame = frame_index;
-                for frame_index in frame_index..(frame_index + DECODE_CHUNK) {
-                    if decoding_frames.contains(&frame_index) {
-                        break;
-                    }
-                    last_frame = frame_index;
-                }
+    /// The frame whose presentation time is closest to `seconds`.
+    fn nearest_frame(&self, seconds: f64) -> Result<u32, DecodeError> {
+        let source = self.source()?;
+        let frame_index = source.seek(seconds)?;
+        let Some(next) = frame_index.checked_add(1) else {
+            return Ok(frame_index);
+        };
+        let past_end = source
+            .metadata()
+            .frame_count
+            .is_some_and(|count| next >= count);
+        match (source.frame_time(frame_index), source.frame_time(next)) {
+            (Some(start), Some(end)) if !past_end && end - seconds < seconds - start => Ok(next),
+            _ => Ok(frame_index),
+        }
+    }
 
-                for frame_index in frame_index..=last_frame {
-                    decoding_frames.insert(frame_index);
-                }
+    /// Source frames shown by project frames `start..=end` at `fps`, in order and without
+    /// repeats. Computed as they are taken, so a long range costs only what is used of it.
+    fn conformed_frames(&self, start: u32, end: u32, fps: f64) -> impl Iterator<Item = u32> {
+        let mut previous = None;
+        (start..=end)
+            .map_while(move |frame| self.nearest_frame(frame as f64 / fps).ok())
+            .filter(move |frame| previous.replace(*frame) != Some(*frame))
+    }
 
-                self.inner
-                    .running_decode_tasks
-                    .fetch_add(1, Ordering::Relaxed);
-
-                let self_clone = self.clone();
-
-                tokio::spawn(async move {
-                    let result = hw_decoder::extract_frame_window_hw_rgba(
-                        &self_clone.inner.path,
-                        frame_index as _,
-                        last_frame as _,
-                        self_clone.inner.width,
-                        self_clone.inner.height,
-                    );
-
-                    match result {
-                        Ok(result) => {
-                            let futures = {
-                                let mut frames = self_clone.inner.frames.write().unwrap();
-
-                                let mut futures = Vec::new();
-                                for (frame_index, _) in result.iter() {
-                                    let future = frames
-                                        .entry(*frame_index as _)
-                                        .or_insert_with(|| SharedManualFuture::new())
-                                        .clone();
-                                    futures.push(future);
-                                }
-
-                                futures
-                            };
-
-                            for (future, (_, frame)) in futures.into_iter().zip(result.into_iter())
-                            {
-                                ENTIRE_CACHE_SIZE.fetch_add(frame.len(), Ordering::Relaxed);
-                                future.complete(Arc::new(frame)).await;
-                            }
-                        }
-                        Err(_) => todo!(),
-                    }
+    /// Project frame `frame` of a timeline at `fps`, counted from the start of the source.
+    /// A decoder that resamples to `fps` already counts in project frames. Otherwise the
+    /// source frames the rest of the window shows are decoded along with it, and only those
+    /// are cached.
+    pub async fn get_conformed_frame(
+        &self,
+        frame: u32,
+        fps: f64,
+        mode: ConformMode,
+        priority: DecodePriority,
+    ) -> Result<DecodedFrame, DecodeError> {
+        self.open_source().await?;
+        if self.inner.output.conform_fps.is_some() {
+            return self.get_frame(frame, priority).await;
+        }
+        let seconds = frame as f64 / fps;
+        if mode == ConformMode::Blend {
+            return self.get_blended_frame(seconds, priority).await;
+        }
 
-                    self_clone
-                        .inner
-                        .running_decode_tasks
-                        .fetch_sub(1, Ordering::Relaxed);
-                });
-            }
+        let source_frame = self.nearest_frame(seconds)?;
+        if !self.source()?.metadata().still {
+            let window = self.conformed_frames(frame, frame.saturating_add(DECODE_CHUNK - 1), fps);
+            self.start_decode(window, priority);
+        }
+        let decoded = self.get_frame(source_frame, priority).await?;
+        Ok(DecodedFrame {
+            timestamp_us: (seconds * 1_000_000.0).round() as i64,
+            ..decoded
+        })
+    }
+
+    /// The picture at `seconds`, mixed from the frame on screen then and the next one by how
+    /// far between them it is. Not cached; the two frames are.
+    pub async fn get_blended_frame(
+        &self,
+        seconds: f64,
+        priority: DecodePriority,
+    ) -> Result<DecodedFrame, DecodeError> {
+        self.open_source().await?;
+        let frame_index = self.frame_at_time(seconds)?;
+        let first = self.get_frame(frame_index, priority).await?;
+
+        let source = self.source()?;
+        let weight = match (
+            source.frame_time(first.source_frame),
+            first
+                .source_frame
+                .checked_add(1)
+                .and_then(|next| source.frame_time(next)),
+        ) {
+            (Some(start), Some(end)) if end > start => (seconds - start) / (end - start),
+            _ => 0.0,
+        };
+        // ほぼ重なっているなら混ぜない
+        if source.metadata().still || !(0.001..=0.999).contains(&weight) {
+            return Ok(first);
         }
 
+        let second = self.get_frame(first.source_frame + 1, priority).await?;
+        if second.source_frame == first.source_frame
+            || second.flags.contains(FrameFlags::PLACEHOLDER)
         {
-            let frame_state = {
-                let mut frame_states = self.inner.frame_states.write().unwrap();
+            return Ok(first);
+        }
 
-                let frame_state = frame_states
-                    .get(&frame_index)
-                    .cloned()
-                    .unwrap_or(FrameState::None);
+        let alpha = self.inner.output.alpha;
+        let (a, b) = (first.rgba.clone(), second.rgba.clone());
+        let rgba = tokio::task::spawn_blocking(move || blend_frames(&a, &b, weight as f32, alpha))
+            .await
+            .map_err(|e| DecodeError::ffmpeg(format!("blend task failed: {e}")))?;
+        Ok(DecodedFrame {
+            rgba: Arc::new(rgba),
+            timestamp_us: (seconds * 1_000_000.0).round() as i64,
+            flags: first.flags | FrameFlags::BLENDED,
+            ..first
+        })
+    }
+
+    /// Output parameters with the size the source decodes at.
+    fn decoded_output(&self) -> Result<OutputParams, DecodeError> {
+        let metadata = self.source()?.metadata();
+        Ok(OutputParams {
+            width: metadata.width,
+            height: metadata.height,
+            ..self.inner.output.clone()
+        })
+    }
 
-                frame_states.insert(frame_index, FrameState::Wait);
+    fn placeholder(&self, frame_index: u32) -> CachedFrame {
+        let (width, height) = self
+            .decoded_output()
+            .map(|output| (output.width, output.height))
+            .unwrap_or_default();
+        CachedFrame {
+            rgba: Arc::new(generate_empty_frame(width, height)),
+            source_frame: frame_index,
+            flags: FrameFlags::PLACEHOLDER,
+        }
+    }
 
-                frame_state
+    /// `frame` (cached as `frame_index`) at this decoder's output size. Scaled down on a
+    /// blocking thread unless the clip is decoded at that size, and cached at that size.
+    async fn decoded_frame(
+        &self,
+        frame_index: u32,
+        frame: &CachedFrame,
+    ) -> Result<DecodedFrame, DecodeError> {
+        let FrameSize { width, height, .. } = self.size;
+        let output = self.decoded_output()?;
+        let decoded = (output.width, output.height);
+
+        let rgba = if frame.flags.contains(FrameFlags::PLACEHOLDER) {
+            Arc::new(generate_empty_frame(width, height))
+        } else if decoded == (width, height) {
+            frame.rgba.clone()
+        } else {
+            let (rgba, size) = (frame.rgba.clone(), self.size);
+            let resized = tokio::task::spawn_blocking(move || {
+                resize_frame(&rgba, decoded, size, output.alpha)
+            })
+            .await
+            .map_err(|e| DecodeError::ffmpeg(format!("resize task failed: {e}")))?;
+            let resized = Arc::new(resized);
+            FRAME_CACHE.insert_resized(
+                self.inner.id,
+                frame_index,
+                self.size,
+                CachedFrame {
+                    rgba: resized.clone(),
+                    ..frame.clone()
+                },
+            );
+            resized
+        };
+        Ok(self.delivered(rgba, frame))
+    }
+
+    fn delivered(&self, rgba: Arc<Vec<u8>>, frame: &CachedFrame) -> DecodedFrame {
+        DecodedFrame {
+            rgba,
+            width: self.size.width,
+            height: self.size.height,
+            source_frame: frame.source_frame,
+            timestamp_us: self.timestamp_us(frame.source_frame),
+            flags: frame.flags,
+        }
+    }
+
+    /// Turn the frames decoded for `window` into one cache entry per frame. A frame the
+    /// source did not output (dropped, or past the end) shows the previous frame, or a
+    /// placeholder if there is none.
+    fn fill_window(&self, window: &[u32], decoded: Vec<(u32, Vec<u8>)>) -> Vec<(u32, CachedFrame)> {
+        let mut decoded = decoded.into_iter().peekable();
+        let mut previous = window
+            .first()
+            .and_then(|start| start.checked_sub(1))
+            .and_then(|prev| FRAME_CACHE.get(self.inner.id, prev));
+
+        let mut frames = Vec::new();
+        for &frame_index in window {
+            while decoded.next_if(|(index, _)| *index < frame_index).is_some() {}
+            let frame = match decoded.next_if(|(index, _)| *index == frame_index) {
+                Some((_, rgba)) => CachedFrame {
+                    rgba: Arc::new(rgba),
+                    source_frame: frame_index,
+                    flags: FrameFlags::NONE,
+                },
+                None => match &previous {
+                    Some(previous) => CachedFrame {
+                        flags: previous.flags | FrameFlags::SUBSTITUTED_PREVIOUS,
+                        ..previous.clone()
+                    },
+                    None => self.placeholder(frame_index),
+                },
             };
+            previous = Some(frame.clone());
+            frames.push((frame_index, frame));
+        }
+        frames
+    }
 
-            if let FrameState::Drop | FrameState::Wait = frame_state {
-                let result = hw_decoder::extract_frame_hw_rgba(
-                    &self.inner.path,
-                    frame_index as _,
-                    self.inner.width,
-                    self.inner.height,
-                );
+    /// Decode the frames of `window`, with `decode_lock` held. Frames found in the disk cache
+    /// are read from there; the rest come from the clip's source, one `decode_range` per run of
+    /// consecutive frames. The source stops between frames when the decoder is closed.
+    fn decode_window(&self, window: &[u32]) -> Result<Vec<(u32, Vec<u8>)>, DecodeError> {
+        let source = self.source()?;
+        let output = self.decoded_output()?;
+        let disk = disk_cache();
+        let clip_key = disk.as_ref().and_then(|disk| {
+            disk.clip_key(
+                &source.files(),
+                &source.filter_graph(),
+                &output,
+                &self.inner.stream,
+            )
+        });
+        let frame_size = output.frame_size();
 
-                match result {
-                    Ok(result) => {
-                        return Arc::new(result);
+        let mut frames = Vec::new();
+        let mut missing = Vec::new();
+        for &frame_index in window {
+            if self.inner.cancel.is_cancelled() {
+                return Err(DecodeError::Cancelled);
+            }
+            match (&disk, &clip_key) {
+                (Some(disk), Some(clip_key)) => {
+                    match disk.load(clip_key, frame_index, frame_size) {
+                        Some(rgba) => frames.push((frame_index, rgba)),
+                        None => missing.push(frame_index),
                     }
-                    Err(_) => todo!(),
+                }
+                _ => missing.push(frame_index),
+            }
+        }
+
+        for run in missing.chunk_by(|a, b| a + 1 == *b) {
+            let (start, end) = (run[0], run[run.len() - 1]);
+            let decoded = source.decode_range(start, end, &self.inner.cancel)?;
+            // 何も出てこなければストリームの終端なので、後ろの区間も読まない
+            if decoded.is_empty() {
+                break;
+            }
+            if let (Some(disk), Some(clip_key)) = (&disk, &clip_key) {
+                for (index, rgba) in &decoded {
+                    disk.store(clip_key, *index, rgba);
                 }
             }
+            frames.extend(decoded);
         }
+        frames.sort_unstable_by_key(|(frame_index, _)| *frame_index);
+        Ok(frames)
+    }
 
-        let future = {
-            let mut frames = self.inner.frames.write().unwrap();
+    /// Start decoding a window of `frames` (in increasing order): from the first frame that is
+    /// not cached or being decoded, up to the next one that is or `DECODE_CHUNK` frames.
+    /// Frames past the end of the clip are left out. Returns the last frame of the window, or
+    /// `None` if every frame is already covered.
+    fn start_decode(
+        &self,
+        frames: impl IntoIterator<Item = u32>,
+        priority: DecodePriority,
+    ) -> Option<(u32, JoinHandle<()>)> {
+        let priority = JobPriority::new(priority);
+        let frame_count = self
+            .source()
+            .ok()
+            .and_then(|source| source.metadata().frame_count);
+        let window = {
+            let mut pending = self.inner.pending.write().unwrap();
+            let covered = |frame_index: &u32| {
+                pending.contains_key(frame_index)
+                    || FRAME_CACHE.contains(self.inner.id, *frame_index)
+            };
 
-            frames
-                .entry(frame_index)
-                .or_insert_with(|| SharedManualFuture::new())
-                .clone()
+            let window = frames
+                .into_iter()
+                .take_while(|frame_index| frame_count.is_none_or(|count| *frame_index < count))
+                .skip_while(covered)
+                .take_while(|frame_index| !covered(frame_index))
+                .take(DECODE_CHUNK as usize)
+                .collect::<Vec<_>>();
+
+            for &frame_index in &window {
+                pending.insert(
+                    frame_index,
+                    PendingFrame {
+                        frame: SharedManualFuture::new(),
+                        priority: priority.clone(),
+                    },
+                );
+            }
+
+            window
         };
+        let &last_frame = window.last()?;
 
-        let frame;
+        self.inner
+            .running_decode_tasks
+            .fetch_add(1, Ordering::Relaxed);
 
-        loop {
-            match timeout(Duration::from_secs(1), future.get()).await {
-                Ok(result) => {
-                    frame = result;
-                    break;
+        let self_clone = self.clone();
+
+        let handle = tokio::spawn(async move {
+            let decoder = self_clone.clone();
+            let frames = window.clone();
+            let decoding = self_clone.inner.decode_lock.clone().lock_owned().await;
+            let result = SCHEDULER
+                .run(priority, move || {
+                    let _decoding = decoding;
+                    decoder.decode_window(&frames)
+                })
+                .await
+                .unwrap_or_else(|e| Err(DecodeError::ffmpeg(format!("decode task failed: {e}"))));
+
+            let result: Vec<(u32, FrameResult)> = match result {
+                Ok(decoded) => self_clone
+                    .fill_window(&window, decoded)
+                    .into_iter()
+                    .map(|(index, frame)| (index, Ok(frame)))
+                    .collect(),
+                Err(e) => {
+                    if e != DecodeError::Cancelled {
+                        error!("failed to decode {}: {e}", self_clone.inner.path);
+                    }
+                    // 待っている全フレームにエラーを返し、次のリクエストで再試行できるようにする
+                    window
+                        .iter()
+                        .map(|index| (*index, Err(e.clone())))
+                        .collect()
                 }
-                Err(_) => match self.inner.running_decode_tasks.load(Ordering::Relaxed) > 0 {
-                    true => continue,
-                    false => {
-                        // 多分ドロップフレーム
-                        // frame_indexに穴がある場合
-                        // 直前のフレームを持ってくる
-                        let mut frame_index = frame_index;
-                        loop {
-                            match frame_index.checked_sub(1) {
-                                Some(new_index) => {
-                                    frame_index = new_index;
-
-                                    let frames = self.inner.frames.read().unwrap();
-
-                                    match frames.get(&frame_index) {
-                                        Some(future) => match future.get_now() {
-                                            Some(result) => {
-                                                frame = result;
-                                                break;
-                                            }
-                                            None => continue,
-                                        },
-                                        None => continue,
-                                    }
-                                }
-                                None => {
-                                    frame = Arc::new(generate_empty_frame(
-                                        self.inner.width,
-                                        self.inner.height,
-                                    ));
-                                    break;
-                                }
-                            }
+            };
+
+            let futures = {
+                let mut pending = self_clone.inner.pending.write().unwrap();
+
+                result
+                    .iter()
+                    .map(|(frame_index, frame)| {
+                        if let Ok(frame) = frame
+                            && !self_clone.inner.cancel.is_cancelled()
+                        {
+                            FRAME_CACHE.insert(self_clone.inner.id, *frame_index, frame.clone());
                         }
+                        pending
+                            .remove(frame_index)
+                            .map(|pending| pending.frame)
+                            .unwrap_or_default()
+                    })
+                    .collect::<Vec<_>>()
+            };
 
-                        break;
-                    }
-                },
+            for (future, (_, frame)) in futures.into_iter().zip(result) {
+                future.complete(Arc::new(frame)).await;
             }
-        }
 
-        {
-            // 送信が終わったフレームは解放する。
-            // ただし、フロントエンドのcurrentFrameの初期値が0なので、
-            // frame_index = 0のリクエストが複数飛んでくる。
-            // 0の場合に解放してしまうと、後方のレスポンスが帰らずに無限に待たせてしまう。
-            // おそらく、もっと良いロジックがあるが、一旦は0のみ解放しないことで実装する。
-            if frame_index != 0 {
-                ENTIRE_CACHE_SIZE.fetch_sub(frame.len(), Ordering::Relaxed);
-
-                self.inner.frames.write().unwrap().remove(&frame_index);
+            self_clone
+                .inner
+                .running_decode_tasks
+                .fetch_sub(1, Ordering::Relaxed);
+        });
+
+        Some((last_frame, handle))
+    }
+
+    /// Decode the first window of `start..=end` that is not cached yet, ahead of time. With
+    /// `project_fps`, `start..=end` are project frames and only the source frames they show are
+    /// cached (unless the decoder resamples, and counts in project frames itself). Returns
+    /// where the rest of the range starts, if any of it is left.
+    async fn prefetch_window(&self, start: u32, end: u32, project_fps: Option<f64>) -> Option<u32> {
+        let source = self.open_source().await.ok()?;
+        if self.inner.cancel.is_cancelled() {
+            return None;
+        }
+        // 静止画はフレーム0だけで足りる
+        if source.metadata().still {
+            if let Some((_, handle)) = self.start_decode([0], DecodePriority::Prefetch) {
+                let _ = handle.await;
             }
+            return None;
         }
 
-        frame
+        let fps = project_fps.filter(|_| self.inner.output.conform_fps.is_none());
+        let (last_frame, handle) = match fps {
+            Some(fps) => self.start_decode(
+                self.conformed_frames(start, end, fps),
+                DecodePriority::Prefetch,
+            ),
+            None => self.start_decode(start..=end, DecodePriority::Prefetch),
+        }?;
+        let _ = handle.await;
+
+        let next = match fps {
+            // 窓の最後より後のソースのフレームを表示する、最初のプロジェクトのフレームから
+            Some(fps) => (start..=end).find(|frame| {
+                self.nearest_frame(*frame as f64 / fps)
+                    .is_ok_and(|source_frame| source_frame > last_frame)
+            })?,
+            None => last_frame.checked_add(1)?,
+        };
+        (next <= end).then_some(next)
+    }
+    /// Frame `frame_index`. A frame past the end of the clip shows the last frame, flagged as
+    /// substituted.
+    pub async fn get_frame(
+        &self,
+        frame_index: u32,
+        priority: DecodePriority,
+    ) -> Result<DecodedFrame, DecodeError> {
+        let metadata = self.open_source().await?.metadata();
+        let (frame_index, past_end) = match metadata.frame_count {
+            _ if metadata.still => (0, false),
+            Some(count) if count > 0 && frame_index >= count => (count - 1, true),
+            _ => (frame_index, false),
+        };
+        let mut frame = self.get_frame_in_range(frame_index, priority).await?;
+        if past_end {
+            frame.flags |= FrameFlags::SUBSTITUTED_PREVIOUS;
+        }
+        Ok(frame)
+    }
+
+    async fn get_frame_in_range(
+        &self,
+        frame_index: u32,
+        priority: DecodePriority,
+    ) -> Result<DecodedFrame, DecodeError> {
+        FRAME_CACHE.set_playhead(self.inner.id, frame_index);
+        if let Some(frame) = FRAME_CACHE.get_resized(self.inner.id, frame_index, self.size) {
+            return Ok(self.delivered(frame.rgba.clone(), &frame));
+        }
+
+        let future = loop {
+            let cached = {
+                let pending = self.inner.pending.read().unwrap();
+                if let Some(pending) = pending.get(&frame_index) {
+                    // 先読みなどの低い優先度で待っている窓なら、この要求の優先度まで上げる
+                    pending.priority.raise(priority);
+                    break pending.frame.clone();
+                }
+                // デコード済みのフレームは pending から消える前にキャッシュへ入るので、
+                // pending をロックしたまま見れば取りこぼさない
+                FRAME_CACHE.get(self.inner.id, frame_index)
+            };
+            if let Some(frame) = cached {
+                return self.decoded_frame(frame_index, &frame).await;
+            }
+
+            self.start_decode(
+                frame_index..frame_index.saturating_add(DECODE_CHUNK),
+                priority,
+            );
+        };
+
+        match future.get().await.as_ref() {
+            Ok(frame) => self.decoded_frame(frame_index, frame).await,
+            Err(e) => Err(e.clone()),
+        }
     }
 }
 
@@ -371,3 +1054,291 @@ pub fn generate_empty_frame(width: u32, height: u32) -> Vec<u8> {
 
     buf
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ffmpeg::filter::ScaleMode, source::SourceMetadata};
+
+    /// Frames whose pixels are their index, 2x1 at 10 fps, counting `decode_range` calls.
+    #[derive(Debug, Default)]
+    struct SyntheticSource {
+        /// `None` for a stream of unknown length that ends at `len`.
+        frame_count: Option<u32>,
+        len: u32,
+        /// Frames the "decoder" drops.
+        dropped: Vec<u32>,
+        /// Fail every call with this reason.
+        fail: Option<&'static str>,
+        /// Block each call until the decoder is closed.
+        block: bool,
+        calls: Mutex<Vec<(u32, u32)>>,
+    }
+
+    impl SyntheticSource {
+        fn frames(count: u32) -> Self {
+            Self {
+                frame_count: Some(count),
+                len: count,
+                ..Default::default()
+            }
+        }
+
+        fn calls(&self) -> Vec<(u32, u32)> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    impl FrameSource for SyntheticSource {
+        fn metadata(&self) -> SourceMetadata {
+            SourceMetadata {
+                frame_count: self.frame_count,
+                fps: Some(10.0),
+                still: false,
+                width: 2,
+                height: 1,
+            }
+        }
+
+        fn seek(&self, seconds: f64) -> Result<u32, DecodeError> {
+            Ok((seconds * 10.0) as u32)
+        }
+
+        fn frame_time(&self, frame: u32) -> Option<f64> {
+            Some(frame as f64 / 10.0)
+        }
+
+        fn decode_range(
+            &self,
+            start: u32,
+            end: u32,
+            cancel: &CancellationToken,
+        ) -> Result<Vec<(u32, Vec<u8>)>, DecodeError> {
+            self.calls.lock().unwrap().push((start, end));
+            if let Some(reason) = self.fail {
+                return Err(DecodeError::ffmpeg(reason));
+            }
+            while self.block && !cancel.is_cancelled() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+
+            let mut frames = Vec::new();
+            for frame in start..=end.min(self.len.saturating_sub(1)) {
+                if cancel.is_cancelled() {
+                    return Err(DecodeError::Cancelled);
+                }
+                if !self.dropped.contains(&frame) {
+                    frames.push((frame, vec![frame as u8; 8]));
+                }
+            }
+            Ok(frames)
+        }
+
+        fn filter_graph(&self) -> String {
+            String::new()
+        }
+
+        fn files(&self) -> Vec<std::path::PathBuf> {
+            Vec::new()
+        }
+    }
+
+    fn decoder_with(source: SyntheticSource) -> (CachedDecoder, Arc<SyntheticSource>) {
+        decoder_at(source, 2, 1)
+    }
+
+    fn decoder_at(
+        source: SyntheticSource,
+        width: u32,
+        height: u32,
+    ) -> (CachedDecoder, Arc<SyntheticSource>) {
+        let key = DecoderKey {
+            path: "synthetic".to_string(),
+            output: OutputParams::default(),
+            source: SourceKind::default(),
+            sequence: SequenceOptions::default(),
+            stream: StreamSelector::default(),
+        };
+        let size = FrameSize {
+            width,
+            height,
+            scale: ScaleMode::Stretch,
+        };
+        let decoder = CachedDecoder::new(key, size);
+        let source = Arc::new(source);
+        *decoder.inner.source.lock().unwrap() = Some(source.clone());
+        (decoder, source)
+    }
+
+    async fn frame(decoder: &CachedDecoder, index: u32) -> DecodedFrame {
+        decoder
+            .get_frame(index, DecodePriority::Interactive)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn a_request_decodes_the_window_after_it_in_one_call() {
+        let (decoder, source) = decoder_with(SyntheticSource::frames(300));
+
+        let first = frame(&decoder, 0).await;
+        assert_eq!(first.rgba[0], 0);
+        assert_eq!(first.timestamp_us, 0);
+        assert_eq!(source.calls(), vec![(0, DECODE_CHUNK - 1)]);
+
+        // 窓の中はキャッシュから返る
+        let cached = frame(&decoder, 5).await;
+        assert_eq!((cached.source_frame, cached.rgba[0]), (5, 5));
+        assert_eq!(cached.timestamp_us, 500_000);
+        assert_eq!(source.calls().len(), 1);
+
+        frame(&decoder, 130).await;
+        assert_eq!(
+            source.calls(),
+            vec![(0, DECODE_CHUNK - 1), (130, 130 + DECODE_CHUNK - 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn windows_stop_at_the_end_of_the_clip() {
+        let (decoder, source) = decoder_with(SyntheticSource::frames(10));
+
+        frame(&decoder, 4).await;
+        assert_eq!(source.calls(), vec![(4, 9)]);
+
+        let past_end = frame(&decoder, 50).await;
+        assert_eq!(past_end.source_frame, 9);
+        assert!(past_end.flags.contains(FrameFlags::SUBSTITUTED_PREVIOUS));
+        assert_eq!(source.calls().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn prefetch_decodes_one_window_and_returns_the_rest() {
+        let (decoder, source) = decoder_with(SyntheticSource::frames(300));
+
+        let rest = decoder.prefetch_window(0, 299, None).await;
+        assert_eq!(rest, Some(DECODE_CHUNK));
+        assert_eq!(source.calls(), vec![(0, DECODE_CHUNK - 1)]);
+
+        // プロジェクトのフレームは 5fps なので、ソースの1フレームおきに読む
+        let rest = decoder.prefetch_window(60, 149, Some(5.0)).await;
+        assert_eq!(rest, None);
+        assert_eq!(source.calls()[1..3], [(120, 120), (122, 122)]);
+        assert!(FRAME_CACHE.contains(decoder.inner.id, 298));
+        assert!(!FRAME_CACHE.contains(decoder.inner.id, 297));
+    }
+
+    #[tokio::test]
+    async fn dropped_frames_show_the_previous_one() {
+        let (decoder, _) = decoder_with(SyntheticSource {
+            dropped: vec![3],
+            ..SyntheticSource::frames(10)
+        });
+
+        frame(&decoder, 0).await;
+        let dropped = frame(&decoder, 3).await;
+        assert_eq!((dropped.source_frame, dropped.rgba[0]), (2, 2));
+        assert!(dropped.flags.contains(FrameFlags::SUBSTITUTED_PREVIOUS));
+        assert_eq!(frame(&decoder, 4).await.flags, FrameFlags::NONE);
+    }
+
+    #[tokio::test]
+    async fn the_first_dropped_frame_is_a_placeholder() {
+        let (decoder, _) = decoder_with(SyntheticSource {
+            dropped: vec![0],
+            ..SyntheticSource::frames(10)
+        });
+
+        let placeholder = frame(&decoder, 0).await;
+        assert!(placeholder.flags.contains(FrameFlags::PLACEHOLDER));
+        assert_eq!(placeholder.rgba.len(), 8);
+    }
+
+    #[test]
+    fn non_consecutive_frames_are_read_in_runs() {
+        let (decoder, source) = decoder_with(SyntheticSource::frames(10));
+
+        let decoded = decoder.decode_window(&[0, 1, 2, 5, 6]).unwrap();
+        assert_eq!(source.calls(), vec![(0, 2), (5, 6)]);
+        assert_eq!(
+            decoded.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+            vec![0, 1, 2, 5, 6]
+        );
+    }
+
+    #[test]
+    fn an_empty_run_ends_the_window() {
+        let (decoder, source) = decoder_with(SyntheticSource {
+            frame_count: None,
+            len: 10,
+            ..Default::default()
+        });
+
+        let decoded = decoder.decode_window(&[8, 9, 10, 11, 20, 21]).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(source.calls(), vec![(8, 11), (20, 21)]);
+
+        source.calls.lock().unwrap().clear();
+        assert!(decoder.decode_window(&[12, 13, 20, 21]).unwrap().is_empty());
+        assert_eq!(source.calls(), vec![(12, 13)]);
+    }
+
+    #[tokio::test]
+    async fn errors_reach_every_waiter_and_are_retried() {
+        let (decoder, source) = decoder_with(SyntheticSource {
+            fail: Some("broken stream"),
+            ..SyntheticSource::frames(10)
+        });
+
+        let (first, second) = tokio::join!(
+            decoder.get_frame(0, DecodePriority::Interactive),
+            decoder.get_frame(1, DecodePriority::Render),
+        );
+        let expected = DecodeError::ffmpeg("broken stream");
+        assert_eq!(first.unwrap_err(), expected);
+        assert_eq!(second.unwrap_err(), expected);
+        assert_eq!(source.calls().len(), 1);
+
+        // 失敗はキャッシュされず、次のリクエストでやり直す
+        let retried = decoder.get_frame(0, DecodePriority::Interactive).await;
+        assert_eq!(retried.unwrap_err(), expected);
+        assert_eq!(source.calls().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn closing_cancels_a_running_window() {
+        let (decoder, source) = decoder_with(SyntheticSource {
+            block: true,
+            ..SyntheticSource::frames(10)
+        });
+
+        let waiting = tokio::spawn({
+            let decoder = decoder.clone();
+            async move { decoder.get_frame(0, DecodePriority::Interactive).await }
+        });
+        while source.calls().is_empty() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        decoder.close();
+
+        assert_eq!(waiting.await.unwrap().unwrap_err(), DecodeError::Cancelled);
+        assert!(!FRAME_CACHE.contains(decoder.inner.id, 0));
+    }
+
+    #[tokio::test]
+    async fn resized_frames_are_cached_per_size() {
+        let (decoder, _) = decoder_at(SyntheticSource::frames(10), 1, 1);
+
+        let resized = frame(&decoder, 2).await;
+        assert_eq!((resized.width, resized.height), (1, 1));
+        assert_eq!(resized.rgba.len(), 4);
+        assert!(
+            FRAME_CACHE
+                .get_resized(decoder.inner.id, 2, decoder.size)
+                .is_some_and(|cached| Arc::ptr_eq(&cached.rgba, &resized.rgba))
+        );
+
+        let again = frame(&decoder, 2).await;
+        assert!(Arc::ptr_eq(&again.rgba, &resized.rgba));
+    }
+}
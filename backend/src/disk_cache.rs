@@ -0,0 +1,411 @@
+use std::{
+    collections::HashMap,
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use tracing::{info, warn};
+
+use crate::ffmpeg::{filter::OutputParams, stream::StreamSelector};
+
+/// Hashed in place of an absent stream selector field.
+const NO_FIELD: &[u8] = &[0];
+
+/// Second-level frame cache on disk. Frames evicted from RAM are read back from here
+/// instead of going through ffmpeg again, also after a restart.
+///
+/// Frames are stored LZ4-compressed, one file per frame, keyed by the BLAKE3 hash of the
+/// source files' paths, sizes and modification times, the ffmpeg filter chain and the frame
+/// index.
+static DISK_CACHE: LazyLock<RwLock<Option<Arc<DiskCache>>>> =
+    LazyLock::new(|| RwLock::new(DiskCache::from_env()));
+
+const FRAME_MAGIC: &[u8; 4] = b"FSDC";
+const FRAME_VERSION: u8 = 1;
+/// Part of every clip key. Bump when decoded frames change for the same filters (pixel format,
+/// decoder flags), so old files are no longer read back.
+const CACHE_FORMAT_VERSION: u32 = 1;
+pub const DEFAULT_MAX_GIB: u64 = 20;
+
+pub fn disk_cache() -> Option<Arc<DiskCache>> {
+    DISK_CACHE.read().unwrap().clone()
+}
+
+/// Enable the disk cache at `dir` (or the default location) with a limit of `max_bytes`,
+/// or disable it with `None`. Files already in the directory are kept and reused.
+pub fn configure_disk_cache(config: Option<(Option<PathBuf>, u64)>) -> io::Result<()> {
+    let cache = match config {
+        Some((dir, max_bytes)) => {
+            let dir = match dir.or_else(default_dir) {
+                Some(dir) => dir,
+                None => return Err(io::Error::other("no cache directory available")),
+            };
+            Some(Arc::new(DiskCache::open(dir, max_bytes)?))
+        }
+        None => None,
+    };
+    *DISK_CACHE.write().unwrap() = cache;
+    Ok(())
+}
+
+/// `<project>/.framescript/cache` if a project root is set, the user cache dir otherwise.
+fn default_dir() -> Option<PathBuf> {
+    let project_root = env::var("FRAMESCRIPT_PROJECT_ROOT")
+        .ok()
+        .filter(|root| !root.trim().is_empty());
+    match project_root {
+        Some(root) => Some(
+            PathBuf::from(root.trim())
+                .join(".framescript")
+                .join("cache"),
+        ),
+        None => dirs::cache_dir().map(|dir| dir.join("framescript")),
+    }
+}
+
+pub struct DiskCache {
+    root: PathBuf,
+    max_size: u64,
+    state: Mutex<DiskState>,
+}
+
+#[derive(Default)]
+struct DiskState {
+    /// File name (without directory) -> entry.
+    entries: HashMap<String, DiskEntry>,
+    size: u64,
+}
+
+struct DiskEntry {
+    len: u64,
+    last_used: SystemTime,
+}
+
+impl DiskCache {
+    fn from_env() -> Option<Arc<Self>> {
+        let value = env::var("FRAMESCRIPT_DISK_CACHE").ok()?;
+        let value = value.trim();
+        let dir = match value {
+            "" | "0" | "off" => return None,
+            "1" | "on" => default_dir()?,
+            dir => PathBuf::from(dir),
+        };
+        let max_gib = env::var("FRAMESCRIPT_DISK_CACHE_GIB")
+            .ok()
+            .and_then(|gib| gib.trim().parse::<u64>().ok())
+            .unwrap_or(DEFAULT_MAX_GIB);
+
+        match Self::open(dir, max_gib * 1024 * 1024 * 1024) {
+            Ok(cache) => Some(Arc::new(cache)),
+            Err(e) => {
+                warn!("failed to open disk cache: {e}");
+                None
+            }
+        }
+    }
+
+    fn open(root: PathBuf, max_size: u64) -> io::Result<Self> {
+        fs::create_dir_all(root.join("frames"))?;
+
+        let mut state = DiskState::default();
+        for shard in fs::read_dir(root.join("frames"))? {
+            let shard = shard?;
+            if !shard.file_type()?.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(shard.path())? {
+                let file = file?;
+                let metadata = file.metadata()?;
+                let name = file.file_name().to_string_lossy().into_owned();
+                if !metadata.is_file() || !name.ends_with(".lz4") {
+                    continue;
+                }
+                let entry = DiskEntry {
+                    len: metadata.len(),
+                    last_used: metadata.modified().unwrap_or(UNIX_EPOCH),
+                };
+                state.size += entry.len;
+                state.entries.insert(name, entry);
+            }
+        }
+
+        info!(
+            "disk cache at {} ({} frames, {} bytes)",
+            root.display(),
+            state.entries.len(),
+            state.size
+        );
+
+        let cache = Self {
+            root,
+            max_size,
+            state: Mutex::new(state),
+        };
+        cache.evict(0);
+        Ok(cache)
+    }
+
+    /// Returns `(used bytes, max bytes)`.
+    pub fn usage(&self) -> (u64, u64) {
+        (self.state.lock().unwrap().size, self.max_size)
+    }
+
+    /// Key prefix for frames decoded from `files` (a clip's file, or every image of a
+    /// sequence) through `filter_graph` from the `stream` video stream. `output` is only read
+    /// for its LUT file. `None` if a file can't be read.
+    pub fn clip_key(
+        &self,
+        files: &[PathBuf],
+        filter_graph: &str,
+        output: &OutputParams,
+        stream: &StreamSelector,
+    ) -> Option<String> {
+        if files.is_empty() {
+            return None;
+        }
+        let mut hasher = blake3::Hasher::new();
+        hasher.update(&CACHE_FORMAT_VERSION.to_le_bytes());
+        // 連番はファイルごとに見るので、1枚だけ上書きされてもキーが変わる
+        for (position, file) in files.iter().enumerate() {
+            if position > 0 {
+                hasher.update(b"\0");
+            }
+            hasher.update(source_key(file)?.as_bytes());
+        }
+        hasher.update(filter_graph.as_bytes());
+        // LUT を書き換えたら別のフレームになる
+        if let Some(lut) = &output.grade.lut {
+            hasher.update(source_key(lut)?.as_bytes());
+        }
+        // 既定のストリームはキーを変えず、既存のキャッシュをそのまま使う
+        if *stream != StreamSelector::default() {
+            let StreamSelector {
+                index,
+                language,
+                title,
+            } = stream;
+            match index {
+                Some(index) => hasher.update(&[1]).update(&index.to_le_bytes()),
+                None => hasher.update(NO_FIELD),
+            };
+            for field in [language, title] {
+                match field {
+                    Some(value) => hasher
+                        .update(&[1])
+                        .update(&(value.len() as u64).to_le_bytes())
+                        .update(value.as_bytes()),
+                    None => hasher.update(NO_FIELD),
+                };
+            }
+        }
+        Some(hasher.finalize().to_hex().to_string())
+    }
+
+    pub fn load(&self, clip_key: &str, frame: u32, frame_size: usize) -> Option<Vec<u8>> {
+        let name = frame_file_name(clip_key, frame);
+        if !self.state.lock().unwrap().entries.contains_key(&name) {
+            return None;
+        }
+
+        let path = self.frame_path(&name);
+        match read_frame_file(&path, frame_size) {
+            Ok(rgba) => {
+                if let Some(entry) = self.state.lock().unwrap().entries.get_mut(&name) {
+                    entry.last_used = SystemTime::now();
+                }
+                // 再起動後も使用順が分かるように更新日時を進める
+                if let Ok(file) = fs::File::options().write(true).open(&path) {
+                    let _ = file.set_modified(SystemTime::now());
+                }
+                Some(rgba)
+            }
+            Err(e) => {
+                warn!(
+                    "dropping unreadable disk cache entry {}: {e}",
+                    path.display()
+                );
+                self.remove(&name);
+                None
+            }
+        }
+    }
+
+    pub fn store(&self, clip_key: &str, frame: u32, rgba: &[u8]) {
+        let name = frame_file_name(clip_key, frame);
+        if self.state.lock().unwrap().entries.contains_key(&name) {
+            return;
+        }
+
+        let path = self.frame_path(&name);
+        let len = match write_frame_file(&path, rgba) {
+            Ok(len) => len,
+            Err(e) => {
+                warn!("failed to write disk cache entry {}: {e}", path.display());
+                return;
+            }
+        };
+
+        self.evict(len);
+        let mut state = self.state.lock().unwrap();
+        state.size += len;
+        state.entries.insert(
+            name,
+            DiskEntry {
+                len,
+                last_used: SystemTime::now(),
+            },
+        );
+    }
+
+    /// Delete least recently used frames until `incoming` more bytes fit. Goes down to 90%
+    /// of the limit so that a full cache does not scan on every write.
+    fn evict(&self, incoming: u64) {
+        let victims = {
+            let mut state = self.state.lock().unwrap();
+            if state.size + incoming <= self.max_size {
+                return;
+            }
+
+            let target = (self.max_size / 10 * 9).saturating_sub(incoming);
+            let mut entries = state
+                .entries
+                .iter()
+                .map(|(name, entry)| (entry.last_used, name.clone()))
+                .collect::<Vec<_>>();
+            entries.sort_unstable();
+
+            let mut victims = Vec::new();
+            for (_, name) in entries {
+                if state.size <= target {
+                    break;
+                }
+                if let Some(entry) = state.entries.remove(&name) {
+                    state.size -= entry.len;
+                    victims.push(name);
+                }
+            }
+            victims
+        };
+
+        for name in victims {
+            let _ = fs::remove_file(self.frame_path(&name));
+        }
+    }
+
+    fn remove(&self, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.entries.remove(name) {
+            state.size -= entry.len;
+        }
+        drop(state);
+        let _ = fs::remove_file(self.frame_path(name));
+    }
+
+    fn frame_path(&self, name: &str) -> PathBuf {
+        self.root.join("frames").join(&name[..2]).join(name)
+    }
+}
+
+/// Identifies the current contents of `path` without reading it: the canonical path, size and
+/// modification time. Editing or replacing the file changes the key.
+fn source_key(path: impl AsRef<Path>) -> Option<String> {
+    let canonical = fs::canonicalize(path).ok()?;
+    let metadata = fs::metadata(&canonical).ok()?;
+    let modified_ns = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos();
+    Some(format!(
+        "{}\0{}\0{modified_ns}",
+        canonical.to_string_lossy(),
+        metadata.len()
+    ))
+}
+
+fn frame_file_name(clip_key: &str, frame: u32) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(clip_key.as_bytes());
+    hasher.update(&frame.to_le_bytes());
+    format!("{}.lz4", hasher.finalize().to_hex())
+}
+
+/// `FSDC`, version u8, then the LZ4 block with the uncompressed size prepended.
+fn write_frame_file(path: &Path, rgba: &[u8]) -> io::Result<u64> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let compressed = lz4_flex::compress_prepend_size(rgba);
+    // 書き込み途中のファイルを読まないように、一時ファイルから置き換える
+    let tmp_path = path.with_extension("tmp");
+    let mut file = fs::File::create(&tmp_path)?;
+    file.write_all(FRAME_MAGIC)?;
+    file.write_all(&[FRAME_VERSION])?;
+    file.write_all(&compressed)?;
+    drop(file);
+    fs::rename(&tmp_path, path)?;
+
+    Ok((FRAME_MAGIC.len() + 1 + compressed.len()) as u64)
+}
+
+fn read_frame_file(path: &Path, frame_size: usize) -> io::Result<Vec<u8>> {
+    let bytes = fs::read(path)?;
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let body = bytes
+        .strip_prefix(FRAME_MAGIC)
+        .and_then(|rest| rest.strip_prefix(&[FRAME_VERSION]))
+        .ok_or_else(|| invalid("bad header"))?;
+    let rgba = lz4_flex::decompress_size_prepended(body).map_err(|e| invalid(&e.to_string()))?;
+    if rgba.len() != frame_size {
+        return Err(invalid("unexpected frame size"));
+    }
+    Ok(rgba)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clip_key_follows_every_file() {
+        let dir = env::temp_dir().join(format!("framescript-disk-key-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let files: Vec<_> = (1..=2)
+            .map(|number| dir.join(format!("s_{number:04}.png")))
+            .collect();
+        for file in &files {
+            fs::write(file, b"a").unwrap();
+        }
+        let cache = DiskCache::open(dir.join("cache"), u64::MAX).unwrap();
+        let key = |files: &[PathBuf], stream: &StreamSelector| {
+            cache.clip_key(files, "scale=2x1", &OutputParams::default(), stream)
+        };
+
+        let before = key(&files, &StreamSelector::default()).unwrap();
+        // 2枚目だけ上書きしてもキーが変わる
+        fs::write(&files[1], b"bb").unwrap();
+        let after = key(&files, &StreamSelector::default()).unwrap();
+        assert_ne!(before, after);
+
+        let language = StreamSelector {
+            language: Some("jpn".to_string()),
+            ..Default::default()
+        };
+        let title = StreamSelector {
+            title: Some("jpn".to_string()),
+            ..Default::default()
+        };
+        assert_ne!(key(&files, &language), key(&files, &title));
+
+        assert!(key(&[], &StreamSelector::default()).is_none());
+        assert!(key(&[dir.join("missing.png")], &StreamSelector::default()).is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
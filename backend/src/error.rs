@@ -0,0 +1,86 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// Why a frame could not be produced. Sent to the client as-is in `/ws` error messages.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "code", rename_all = "snake_case")]
+pub enum DecodeError {
+    /// The requested path could not be resolved.
+    InvalidPath {
+        path: String,
+        reason: String,
+    },
+    FileNotFound {
+        path: String,
+    },
+    NoVideoStream {
+        path: String,
+    },
+    /// The frame is past the end of the clip.
+    FrameOutOfRange {
+        path: String,
+        frame: u32,
+        frame_count: u32,
+    },
+    /// ffmpeg/ffprobe binary could not be located or started.
+    FfmpegUnavailable {
+        reason: String,
+    },
+    /// ffmpeg ran but failed. `stderr` is the captured log output.
+    Ffmpeg {
+        reason: String,
+        status: Option<i32>,
+        stderr: String,
+    },
+    /// The decoder was closed (reset or idle eviction) before the frame was ready.
+    Cancelled,
+}
+
+impl DecodeError {
+    pub(crate) fn ffmpeg(reason: impl Into<String>) -> Self {
+        DecodeError::Ffmpeg {
+            reason: reason.into(),
+            status: None,
+            stderr: String::new(),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::InvalidPath { path, reason } => {
+                write!(f, "invalid path {path}: {reason}")
+            }
+            DecodeError::FileNotFound { path } => write!(f, "file not found: {path}"),
+            DecodeError::NoVideoStream { path } => write!(f, "no video stream: {path}"),
+            DecodeError::FrameOutOfRange {
+                path,
+                frame,
+                frame_count,
+            } => write!(
+                f,
+                "frame {frame} is past the end of {path} ({frame_count} frames)"
+            ),
+            DecodeError::FfmpegUnavailable { reason } => write!(f, "{reason}"),
+            DecodeError::Cancelled => write!(f, "decoder closed"),
+            DecodeError::Ffmpeg {
+                reason,
+                status,
+                stderr,
+            } => {
+                write!(f, "{reason}")?;
+                if let Some(status) = status {
+                    write!(f, " (exit status {status})")?;
+                }
+                if !stderr.is_empty() {
+                    write!(f, ": {}", stderr.trim())?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
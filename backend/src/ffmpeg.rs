@@ -1,31 +1,85 @@
-pub mod hw_decoder;
-pub mod sw_decoder;
-pub(crate) mod command;
 pub(crate) mod bin;
+pub mod color;
+pub(crate) mod command;
+pub mod deinterlace;
+pub mod filter;
+pub mod grade;
+pub mod index;
+pub mod key;
+#[cfg(feature = "libav")]
+pub(crate) mod libav;
+pub mod orientation;
+pub(crate) mod reader;
+pub mod session;
+pub mod stream;
 
 use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
 use std::process::Command;
 
+use color::SourceColor;
+use deinterlace::FieldOrder;
+use orientation::{Orientation, parse_display_matrix};
+use stream::{MediaKind, stream_specifier};
+
 #[derive(Debug, Deserialize)]
 struct FfprobeFormat {
     duration: Option<String>,
+    start_time: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct FfprobeStream {
+    index: Option<u32>,
+    codec_type: Option<String>,
     duration: Option<String>,
     avg_frame_rate: Option<String>,
     r_frame_rate: Option<String>,
     nb_frames: Option<String>,
+    codec_name: Option<String>,
+    pix_fmt: Option<String>,
+    field_order: Option<String>,
+    width: Option<u32>,
+    height: Option<u32>,
+    color_space: Option<String>,
+    color_range: Option<String>,
+    color_transfer: Option<String>,
+    color_primaries: Option<String>,
+    channels: Option<u32>,
+    channel_layout: Option<String>,
+    sample_rate: Option<String>,
+    tags: Option<HashMap<String, String>>,
+    disposition: Option<HashMap<String, i32>>,
+    side_data_list: Option<Vec<FfprobeSideData>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FfprobeSideData {
+    displaymatrix: Option<String>,
+    rotation: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FfprobePacket {
+    pts_time: Option<String>,
+    dts_time: Option<String>,
+    duration_time: Option<String>,
+    flags: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct FfprobeOutput {
     format: Option<FfprobeFormat>,
     streams: Option<Vec<FfprobeStream>>,
+    packets: Option<Vec<FfprobePacket>>,
 }
 
-fn run_ffprobe(path: &str, select_streams: Option<&str>, entries: &str) -> Result<FfprobeOutput, String> {
+fn run_ffprobe(
+    path: &str,
+    select_streams: Option<&str>,
+    entries: &str,
+) -> Result<FfprobeOutput, String> {
     let ffprobe = bin::ffprobe_path()?;
     let mut cmd = Command::new(ffprobe);
     cmd.arg("-v")
@@ -91,9 +145,21 @@ fn parse_ratio(value: Option<&str>) -> Option<f64> {
     }
 }
 
-/// Return video duration in milliseconds using ffprobe metadata.
-pub fn probe_video_duration_ms(path: &str) -> Result<u64, String> {
-    let output = run_ffprobe(path, Some("v:0"), "format=duration:stream=duration")?;
+/// Parse an ffprobe rate like `30000/1001` into a positive fraction.
+fn parse_rational(value: Option<&str>) -> Option<(u32, u32)> {
+    let (num, den) = value?.trim().split_once('/')?;
+    let num = num.trim().parse::<u32>().ok()?;
+    let den = den.trim().parse::<u32>().ok()?;
+    (num > 0 && den > 0).then_some((num, den))
+}
+
+/// Return the duration of the `stream`-th video stream in milliseconds using ffprobe metadata.
+pub fn probe_video_duration_ms(path: &str, stream: u32) -> Result<u64, String> {
+    let output = run_ffprobe(
+        path,
+        Some(&stream_specifier(MediaKind::Video, stream)),
+        "format=duration:stream=duration",
+    )?;
     let stream_duration = output
         .streams
         .as_ref()
@@ -104,22 +170,31 @@ pub fn probe_video_duration_ms(path: &str) -> Result<u64, String> {
         .as_ref()
         .and_then(|format| parse_duration_seconds(format.duration.as_deref()));
 
-    let seconds = stream_duration.or(format_duration).ok_or_else(|| "failed to read duration".to_string())?;
+    let seconds = stream_duration
+        .or(format_duration)
+        .ok_or_else(|| "failed to read duration".to_string())?;
     Ok((seconds * 1000.0).round().max(0.0) as u64)
 }
 
-pub fn probe_video_frames(path: &str) -> Result<u64, String> {
-    let output = run_ffprobe(path, Some("v:0"), "stream=nb_frames,duration,avg_frame_rate")?;
+pub fn probe_video_frames(path: &str, stream: u32) -> Result<u64, String> {
+    let output = run_ffprobe(
+        path,
+        Some(&stream_specifier(MediaKind::Video, stream)),
+        "stream=nb_frames,duration,avg_frame_rate",
+    )?;
     let stream = output
         .streams
         .as_ref()
         .and_then(|streams| streams.first())
         .ok_or_else(|| "failed to read frames".to_string())?;
 
-    if let Some(frames) = stream.nb_frames.as_deref().and_then(|value| value.parse::<u64>().ok()) {
-        if frames > 0 {
-            return Ok(frames);
-        }
+    if let Some(frames) = stream
+        .nb_frames
+        .as_deref()
+        .and_then(|value| value.parse::<u64>().ok())
+        && frames > 0
+    {
+        return Ok(frames);
     }
 
     let duration = parse_duration_seconds(stream.duration.as_deref());
@@ -131,8 +206,12 @@ pub fn probe_video_frames(path: &str) -> Result<u64, String> {
     Err("failed to read frames".to_string())
 }
 
-pub fn probe_video_fps(path: &str) -> Result<f64, String> {
-    let output = run_ffprobe(path, Some("v:0"), "stream=avg_frame_rate,r_frame_rate")?;
+pub fn probe_video_fps(path: &str, stream: u32) -> Result<f64, String> {
+    let output = run_ffprobe(
+        path,
+        Some(&stream_specifier(MediaKind::Video, stream)),
+        "stream=avg_frame_rate,r_frame_rate",
+    )?;
     let stream = output
         .streams
         .as_ref()
@@ -146,12 +225,231 @@ pub fn probe_video_fps(path: &str) -> Result<f64, String> {
     Ok(fps)
 }
 
-/// Return audio duration in milliseconds using ffprobe metadata.
-pub fn probe_audio_duration_ms(path: &str) -> Result<u64, String> {
+/// What decoding needs to know about a video stream beyond its frames.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamInfo {
+    /// Position among the file's video streams (`v:N`).
+    pub index: u32,
+    pub alpha: StreamAlpha,
+    pub orientation: Orientation,
+    pub color: SourceColor,
+    pub field_order: FieldOrder,
+    /// Coded size, before `orientation` is applied.
+    pub width: u32,
+    pub height: u32,
+    /// Average frame rate as `(num, den)`, if reported.
+    pub frame_rate: Option<(u32, u32)>,
+}
+
+/// ffprobe entries `StreamInfo::of` reads.
+const STREAM_INFO_ENTRIES: &str = "stream=codec_name,pix_fmt,field_order,width,height,avg_frame_rate,r_frame_rate,color_space,color_range,color_transfer,color_primaries:stream_tags=alpha_mode,rotate:stream_side_data=displaymatrix,rotation";
+
+impl StreamInfo {
+    /// `path` is read for palette transparency, which ffprobe doesn't report.
+    fn of(path: &str, index: u32, stream: &FfprobeStream) -> Self {
+        Self {
+            index,
+            alpha: StreamAlpha::of(path, stream),
+            orientation: stream_orientation(stream),
+            color: SourceColor::from_tags(
+                stream.color_space.as_deref(),
+                stream.color_range.as_deref(),
+                stream.color_transfer.as_deref(),
+                stream.color_primaries.as_deref(),
+                stream.pix_fmt.as_deref(),
+            ),
+            field_order: FieldOrder::from_probe(stream.field_order.as_deref()),
+            width: stream.width.unwrap_or(0),
+            height: stream.height.unwrap_or(0),
+            frame_rate: parse_rational(stream.avg_frame_rate.as_deref())
+                .or_else(|| parse_rational(stream.r_frame_rate.as_deref())),
+        }
+    }
+
+    /// Size as displayed, after rotation.
+    pub fn display_size(&self) -> (u32, u32) {
+        if self.orientation.swaps_dimensions() {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+}
+
+fn stream_orientation(stream: &FfprobeStream) -> Orientation {
+    let side_data = stream.side_data_list.iter().flatten();
+    for side_data in side_data.clone() {
+        if let Some(matrix) = side_data
+            .displaymatrix
+            .as_deref()
+            .and_then(parse_display_matrix)
+        {
+            return Orientation::from_matrix(matrix);
+        }
+    }
+    for side_data in side_data {
+        if let Some(rotation) = side_data.rotation {
+            return Orientation::from_rotation(rotation);
+        }
+    }
+    // 古いFFmpegはタグ (時計回りの角度) で報告する
+    stream
+        .tags
+        .as_ref()
+        .and_then(|tags| tags.get("rotate"))
+        .and_then(|rotate| rotate.trim().parse::<f64>().ok())
+        .map(|rotate| Orientation::from_rotation(-rotate))
+        .unwrap_or_default()
+}
+
+/// Alpha channel of a video stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamAlpha {
+    pub has_alpha: bool,
+    /// Decoder to use instead of the default one, which would drop the alpha (VP8/VP9).
+    pub decoder: Option<&'static str>,
+}
+
+impl StreamAlpha {
+    fn of(path: &str, stream: &FfprobeStream) -> Self {
+        // VP8/VP9 のアルファは別ストリーム (BlockAdditional) にあり、内蔵デコーダは読まない
+        let alpha_mode = stream.tags.as_ref().is_some_and(|tags| {
+            tags.iter()
+                .any(|(key, value)| key.eq_ignore_ascii_case("alpha_mode") && value.trim() == "1")
+        });
+        let decoder = match stream.codec_name.as_deref() {
+            Some("vp9") if alpha_mode => Some("libvpx-vp9"),
+            Some("vp8") if alpha_mode => Some("libvpx"),
+            _ => None,
+        };
+        let pix_fmt_alpha = match stream.pix_fmt.as_deref() {
+            // パレットは透明色があるときだけアルファとして扱う
+            Some("pal8") => palette_has_transparency(path),
+            Some(pix_fmt) => pix_fmt_has_alpha(pix_fmt),
+            None => false,
+        };
+        Self {
+            has_alpha: alpha_mode || pix_fmt_alpha,
+            decoder,
+        }
+    }
+}
+
+/// Whether an ffmpeg pixel format carries alpha (ProRes 4444 `yuva444p12le`, QuickTime
+/// Animation `argb`, PNG `rgba` ...). Palette formats are checked per file by
+/// `palette_has_transparency`.
+fn pix_fmt_has_alpha(pix_fmt: &str) -> bool {
+    const ALPHA_PREFIXES: &[&str] = &["yuva", "gbrap", "ya", "rgba", "bgra", "argb", "abgr"];
+    ALPHA_PREFIXES
+        .iter()
+        .any(|prefix| pix_fmt.starts_with(prefix))
+}
+
+/// Whether the palette image at `path` has a transparent color: a GIF graphic control
+/// extension with the transparency flag set, or a PNG `tRNS` chunk. Other files are opaque.
+fn palette_has_transparency(path: &str) -> bool {
+    let Ok(bytes) = fs::read(path) else {
+        return false;
+    };
+    if bytes.starts_with(b"GIF8") {
+        gif_has_transparency(&bytes).unwrap_or(false)
+    } else if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_has_transparency(&bytes).unwrap_or(false)
+    } else {
+        false
+    }
+}
+
+/// Walks the GIF blocks up to the first transparent graphic control extension. `None` if the
+/// file ends early.
+fn gif_has_transparency(bytes: &[u8]) -> Option<bool> {
+    let color_table = |packed: u8| {
+        if packed & 0x80 != 0 {
+            3 << ((packed & 0x07) + 1)
+        } else {
+            0
+        }
+    };
+    // ヘッダ6バイトと論理画面記述子7バイト
+    let mut pos = 13 + color_table(*bytes.get(10)?);
+    loop {
+        match *bytes.get(pos)? {
+            // 拡張ブロック
+            0x21 => {
+                let label = *bytes.get(pos + 1)?;
+                if label == 0xF9 && bytes.get(pos + 2)? >= &4 && bytes.get(pos + 3)? & 0x01 != 0 {
+                    return Some(true);
+                }
+                pos = skip_gif_sub_blocks(bytes, pos + 2)?;
+            }
+            // 画像記述子、ローカルカラーテーブル、LZWの最小コードサイズ、画像データ
+            0x2C => {
+                let packed = *bytes.get(pos + 9)?;
+                pos = skip_gif_sub_blocks(bytes, pos + 10 + color_table(packed) + 1)?;
+            }
+            _ => return Some(false),
+        }
+    }
+}
+
+/// Position after the sub-blocks starting at `pos` and their terminator.
+fn skip_gif_sub_blocks(bytes: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = *bytes.get(pos)? as usize;
+        pos += 1 + len;
+        if len == 0 {
+            return Some(pos);
+        }
+    }
+}
+
+/// Looks for a `tRNS` chunk, which has to come before the image data.
+fn png_has_transparency(bytes: &[u8]) -> Option<bool> {
+    let mut pos = 8;
+    loop {
+        let len = u32::from_be_bytes(bytes.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        match bytes.get(pos + 4..pos + 8)? {
+            b"tRNS" => return Some(true),
+            b"IDAT" | b"IEND" => return Some(false),
+            _ => pos += 12 + len,
+        }
+    }
+}
+
+/// Return the alpha, orientation and size of the `stream`-th video stream.
+pub fn probe_stream_info(path: &str, stream: u32) -> Result<StreamInfo, String> {
+    let output = run_ffprobe(
+        path,
+        Some(&stream_specifier(MediaKind::Video, stream)),
+        STREAM_INFO_ENTRIES,
+    )?;
+    output
+        .streams
+        .as_ref()
+        .and_then(|streams| streams.first())
+        .map(|info| StreamInfo::of(path, stream, info))
+        .ok_or_else(|| "Not video!".to_string())
+}
+
+/// Return whether the file has at least one video stream.
+pub fn probe_has_video_stream(path: &str) -> Result<bool, String> {
+    let output = run_ffprobe(path, Some("v"), "stream=index")?;
+    Ok(output
+        .streams
+        .as_ref()
+        .is_some_and(|streams| !streams.is_empty()))
+}
+
+/// Return the duration of the `stream`-th audio stream in milliseconds using ffprobe metadata.
+pub fn probe_audio_duration_ms(path: &str, stream: u32) -> Result<u64, String> {
     // Some containers report bogus global duration; prefer audio stream duration when available.
     const MAX_REASONABLE_DURATION_MS: u64 = 1000 * 60 * 60 * 24 * 7; // 7 days
 
-    let output = run_ffprobe(path, Some("a:0"), "format=duration:stream=duration")?;
+    let output = run_ffprobe(
+        path,
+        Some(&stream_specifier(MediaKind::Audio, stream)),
+        "format=duration:stream=duration",
+    )?;
     let stream_duration = output
         .streams
         .as_ref()
@@ -171,3 +469,53 @@ pub fn probe_audio_duration_ms(path: &str) -> Result<u64, String> {
 
     Err("failed to read audio duration".to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1x1 GIF with a global color table of two colors and one graphic control extension.
+    fn gif(transparent: bool) -> Vec<u8> {
+        let mut bytes = b"GIF89a".to_vec();
+        bytes.extend([1, 0, 1, 0, 0x80, 0, 0]);
+        bytes.extend([0, 0, 0, 255, 255, 255]);
+        bytes.extend([0x21, 0xF9, 4, transparent as u8, 0, 0, 0, 0]);
+        bytes.extend([0x2C, 0, 0, 0, 0, 1, 0, 1, 0, 0]);
+        bytes.extend([2, 2, 0x4C, 0x01, 0]);
+        bytes.push(0x3B);
+        bytes
+    }
+
+    fn png(chunks: &[&[u8; 4]]) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n".to_vec();
+        for chunk in chunks {
+            bytes.extend(1u32.to_be_bytes());
+            bytes.extend(*chunk);
+            bytes.extend([0, 0, 0, 0, 0]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn gif_transparency_comes_from_the_graphic_control_extension() {
+        assert_eq!(gif_has_transparency(&gif(true)), Some(true));
+        assert_eq!(gif_has_transparency(&gif(false)), Some(false));
+    }
+
+    #[test]
+    fn png_transparency_needs_trns_before_the_image_data() {
+        assert_eq!(
+            png_has_transparency(&png(&[b"IHDR", b"PLTE", b"tRNS", b"IDAT"])),
+            Some(true)
+        );
+        assert_eq!(
+            png_has_transparency(&png(&[b"IHDR", b"PLTE", b"IDAT", b"IEND"])),
+            Some(false)
+        );
+    }
+
+    #[test]
+    fn truncated_gif_is_not_transparent() {
+        assert_eq!(gif_has_transparency(&gif(true)[..20]), None);
+    }
+}
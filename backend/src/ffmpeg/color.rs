@@ -0,0 +1,201 @@
+use serde::{Deserialize, Serialize};
+
+/// YUV to RGB coefficients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorMatrix {
+    Bt601,
+    Bt709,
+    Bt2020,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorRange {
+    /// 16-235 (`tv`).
+    Limited,
+    /// 0-255 (`pc`).
+    Full,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transfer {
+    #[default]
+    Sdr,
+    /// SMPTE ST 2084 (HDR10, Dolby Vision).
+    Pq,
+    /// ARIB STD-B67 (broadcast HDR).
+    Hlg,
+}
+
+/// How HDR sources are brought into SDR range.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapping {
+    #[default]
+    Hable,
+    Mobius,
+    Reinhard,
+    /// No curve; highlights are clipped.
+    Clip,
+}
+
+/// Color tags of a source stream, as ffprobe reports them. Missing tags are `None`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceColor {
+    pub matrix: Option<ColorMatrix>,
+    pub range: Option<ColorRange>,
+    pub transfer: Transfer,
+    /// BT.2020 primaries, which need a gamut conversion to BT.709.
+    pub wide_gamut: bool,
+    /// The pixels are already RGB (or palette), so no matrix applies.
+    pub rgb: bool,
+}
+
+impl SourceColor {
+    pub(crate) fn from_tags(
+        color_space: Option<&str>,
+        color_range: Option<&str>,
+        color_transfer: Option<&str>,
+        color_primaries: Option<&str>,
+        pix_fmt: Option<&str>,
+    ) -> Self {
+        let matrix = match color_space {
+            Some("bt709") => Some(ColorMatrix::Bt709),
+            Some("smpte170m" | "bt470bg" | "fcc") => Some(ColorMatrix::Bt601),
+            Some("bt2020nc" | "bt2020c") => Some(ColorMatrix::Bt2020),
+            _ => None,
+        };
+        let range = match color_range {
+            Some("tv") => Some(ColorRange::Limited),
+            Some("pc") => Some(ColorRange::Full),
+            // yuvj* は JPEG 由来のフルレンジ
+            _ if pix_fmt.is_some_and(|pix_fmt| pix_fmt.starts_with("yuvj")) => {
+                Some(ColorRange::Full)
+            }
+            _ => None,
+        };
+        let transfer = match color_transfer {
+            Some("smpte2084") => Transfer::Pq,
+            Some("arib-std-b67") => Transfer::Hlg,
+            _ => Transfer::Sdr,
+        };
+        const RGB_PREFIXES: &[&str] = &["rgb", "bgr", "gbr", "argb", "abgr", "pal8"];
+        Self {
+            matrix,
+            range,
+            transfer,
+            wide_gamut: color_primaries == Some("bt2020"),
+            rgb: pix_fmt.is_some_and(|pix_fmt| {
+                RGB_PREFIXES
+                    .iter()
+                    .any(|prefix| pix_fmt.starts_with(prefix))
+            }),
+        }
+    }
+
+    /// The conversion to full-range BT.709 / sRGB used for a source `height` pixels tall.
+    /// Untagged sources are treated like players treat them: BT.709 from 720 lines up,
+    /// BT.601 below, limited range.
+    pub fn conversion(&self, height: u32, tonemap: ToneMapping) -> ColorConversion {
+        if self.rgb {
+            return ColorConversion::default();
+        }
+
+        let guessed = self.matrix.is_none() || self.range.is_none();
+        let default_matrix = match self.transfer {
+            Transfer::Pq | Transfer::Hlg => ColorMatrix::Bt2020,
+            Transfer::Sdr if height >= 720 => ColorMatrix::Bt709,
+            Transfer::Sdr => ColorMatrix::Bt601,
+        };
+        let hdr = self.transfer != Transfer::Sdr;
+        ColorConversion {
+            matrix: Some(self.matrix.unwrap_or(default_matrix)),
+            range: Some(self.range.unwrap_or(ColorRange::Limited)),
+            transfer: self.transfer,
+            wide_gamut: self.wide_gamut || hdr,
+            tonemap: hdr.then_some(tonemap),
+            guessed,
+        }
+    }
+}
+
+/// How a source is converted to the RGBA output. Reported by `GET /video/meta`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize)]
+pub struct ColorConversion {
+    /// `None` for RGB sources, which are passed through.
+    pub matrix: Option<ColorMatrix>,
+    pub range: Option<ColorRange>,
+    pub transfer: Transfer,
+    /// Converted from BT.2020 primaries to BT.709.
+    pub wide_gamut: bool,
+    /// Set for HDR sources.
+    pub tonemap: Option<ToneMapping>,
+    /// Some of the tags were missing and had to be guessed.
+    pub guessed: bool,
+}
+
+impl ColorConversion {
+    /// Filters converting the decoded picture to full-range RGB (`gbrp`, or `gbrap` with
+    /// alpha). SDR BT.709/601 uses swscale with explicit coefficients; wide gamut and HDR go
+    /// through zscale (FFmpeg built with libzimg), linearized for tone mapping.
+    pub fn filters(&self, alpha: bool) -> Vec<String> {
+        let (Some(matrix), Some(range)) = (self.matrix, self.range) else {
+            return Vec::new();
+        };
+        let (rgb, rgb_float) = if alpha {
+            ("gbrap", "gbrapf32le")
+        } else {
+            ("gbrp", "gbrpf32le")
+        };
+
+        if !self.wide_gamut {
+            let matrix = match matrix {
+                ColorMatrix::Bt601 => "bt601",
+                ColorMatrix::Bt709 => "bt709",
+                ColorMatrix::Bt2020 => "bt2020",
+            };
+            let range = match range {
+                ColorRange::Limited => "tv",
+                ColorRange::Full => "pc",
+            };
+            return vec![
+                format!("scale=in_color_matrix={matrix}:in_range={range}:out_range=pc"),
+                format!("format={rgb}"),
+            ];
+        }
+
+        let matrix_in = match matrix {
+            ColorMatrix::Bt601 => "170m",
+            ColorMatrix::Bt709 => "709",
+            ColorMatrix::Bt2020 => "2020_ncl",
+        };
+        let range_in = match range {
+            ColorRange::Limited => "limited",
+            ColorRange::Full => "full",
+        };
+        let transfer_in = match self.transfer {
+            Transfer::Sdr => "709",
+            Transfer::Pq => "smpte2084",
+            Transfer::Hlg => "arib-std-b67",
+        };
+
+        let mut filters = vec![
+            format!(
+                "zscale=min={matrix_in}:rin={range_in}:tin={transfer_in}:pin=2020:t=linear:npl=100"
+            ),
+            format!("format={rgb_float}"),
+            "zscale=p=709".to_string(),
+        ];
+        match self.tonemap {
+            Some(ToneMapping::Clip) | None => {}
+            Some(ToneMapping::Hable) => filters.push("tonemap=hable:desat=0".to_string()),
+            Some(ToneMapping::Mobius) => filters.push("tonemap=mobius:desat=0".to_string()),
+            Some(ToneMapping::Reinhard) => filters.push("tonemap=reinhard:desat=0".to_string()),
+        }
+        filters.push("zscale=t=709:m=709:r=full".to_string());
+        filters.push(format!("format={rgb}"));
+        filters
+    }
+}
@@ -1,42 +1,153 @@
 use std::io::{self, Read};
-use std::process::{Command, Stdio};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::thread::{self, JoinHandle};
 
+use crate::error::DecodeError;
+use crate::ffmpeg::StreamInfo;
 use crate::ffmpeg::bin::ffmpeg_path;
+use crate::ffmpeg::filter::{ConformStart, OutputParams};
+use crate::ffmpeg::reader::FrameReader;
 
-pub(crate) fn extract_frames_rgba(
+/// A running ffmpeg process writing rawvideo RGBA frames to its stdout.
+#[derive(Debug)]
+pub(crate) struct RawFrameReader {
+    child: Child,
+    stdout: ChildStdout,
+    stderr_reader: Option<JoinHandle<String>>,
+    frame_size: usize,
+}
+
+impl FrameReader for RawFrameReader {
+    /// Read the next frame. Returns `None` once ffmpeg has finished successfully.
+    fn read_frame(&mut self) -> Result<Option<Vec<u8>>, DecodeError> {
+        let mut frame = vec![0u8; self.frame_size];
+        match self.stdout.read_exact(&mut frame) {
+            Ok(()) => Ok(Some(frame)),
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                let status = self.child.wait().map_err(|error| {
+                    DecodeError::ffmpeg(format!("failed to wait on ffmpeg: {error}"))
+                })?;
+                if status.success() {
+                    Ok(None)
+                } else {
+                    Err(DecodeError::Ffmpeg {
+                        reason: "ffmpeg failed".to_string(),
+                        status: status.code(),
+                        stderr: self.take_stderr(),
+                    })
+                }
+            }
+            Err(error) => {
+                let _ = self.child.kill();
+                let _ = self.child.wait();
+                Err(DecodeError::Ffmpeg {
+                    reason: format!("failed to read ffmpeg output: {error}"),
+                    status: None,
+                    stderr: self.take_stderr(),
+                })
+            }
+        }
+    }
+}
+
+impl RawFrameReader {
+    fn take_stderr(&mut self) -> String {
+        self.stderr_reader
+            .take()
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for RawFrameReader {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Start ffmpeg decoding `path` from `seek_seconds` (input seek, so only the GOP before the
+/// seek point is decoded) and streaming every following frame.
+pub(crate) fn spawn_rgba_reader(
     path: &str,
-    start_frame: usize,
-    end_frame: usize,
-    dst_width: u32,
-    dst_height: u32,
+    seek_seconds: Option<f64>,
+    output: &OutputParams,
+    conform: ConformStart,
     use_hwaccel: bool,
-) -> Result<Vec<Vec<u8>>, String> {
-    if end_frame < start_frame {
-        return Ok(Vec::new());
-    }
-    let frame_size = (dst_width as usize)
-        .saturating_mul(dst_height as usize)
-        .saturating_mul(4);
+    stream: StreamInfo,
+) -> Result<RawFrameReader, DecodeError> {
+    spawn_reader(path, output, conform, stream, |cmd| {
+        if use_hwaccel {
+            cmd.arg("-hwaccel").arg("auto");
+        }
+        if let Some(seek_seconds) = seek_seconds.filter(|seconds| *seconds > 0.0) {
+            cmd.arg("-ss").arg(format!("{seek_seconds:.6}"));
+        }
+    })
+}
+
+/// Where ffmpeg starts reading an image sequence, and the rate it plays it at.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct SequenceInput {
+    /// File number of the first image read.
+    pub start_number: u32,
+    pub fps: f64,
+}
+
+/// Start ffmpeg reading the numbered images matching `pattern` (`shot_%04d.png`) with the
+/// image2 demuxer, from `input.start_number` on. The stream ends before the first missing
+/// number.
+pub(crate) fn spawn_sequence_reader(
+    pattern: &str,
+    input: SequenceInput,
+    output: &OutputParams,
+    stream: StreamInfo,
+) -> Result<RawFrameReader, DecodeError> {
+    spawn_reader(pattern, output, ConformStart::default(), stream, |cmd| {
+        // glob として解釈されないように、パターンは `%d` の連番としてだけ扱わせる
+        cmd.arg("-f")
+            .arg("image2")
+            .arg("-pattern_type")
+            .arg("sequence")
+            .arg("-framerate")
+            .arg(input.fps.to_string())
+            .arg("-start_number")
+            .arg(input.start_number.to_string());
+    })
+}
+
+/// Start ffmpeg on `input`, after the input options added by `input_args`, writing
+/// output-sized RGBA frames to its stdout.
+fn spawn_reader(
+    input: &str,
+    output: &OutputParams,
+    conform: ConformStart,
+    stream: StreamInfo,
+    input_args: impl FnOnce(&mut Command),
+) -> Result<RawFrameReader, DecodeError> {
+    let frame_size = output.frame_size();
     if frame_size == 0 {
-        return Err("invalid output size".to_string());
+        return Err(DecodeError::ffmpeg("invalid output size"));
     }
 
-    let filter = format!(
-        "trim=start_frame={}:end_frame={},scale={}x{}",
-        start_frame, end_frame, dst_width, dst_height
-    );
+    let filter = output.filters_from(stream, conform).join(",");
 
-    let ffmpeg = ffmpeg_path()?;
+    let ffmpeg = ffmpeg_path().map_err(|reason| DecodeError::FfmpegUnavailable { reason })?;
     let mut cmd = Command::new(ffmpeg);
     cmd.arg("-hide_banner")
         .arg("-loglevel")
         .arg("error")
         .arg("-nostdin");
-    if use_hwaccel {
-        cmd.arg("-hwaccel").arg("auto");
+    input_args(&mut cmd);
+    if let Some(decoder) = stream.alpha.decoder {
+        cmd.arg("-c:v").arg(decoder);
     }
+    // 回転は filters() で自前で行う
+    cmd.arg("-noautorotate");
     cmd.arg("-i")
-        .arg(path)
+        .arg(input)
+        .arg("-map")
+        .arg(format!("0:v:{}", stream.index))
         .arg("-vf")
         .arg(filter)
         .arg("-an")
@@ -48,44 +159,31 @@ pub(crate) fn extract_frames_rgba(
         .arg("rgba")
         .arg("pipe:1");
 
-    cmd.stdout(Stdio::piped()).stderr(Stdio::inherit());
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
 
     let mut child = cmd
         .spawn()
-        .map_err(|error| format!("failed to run ffmpeg: {error}"))?;
-    let mut stdout = child
+        .map_err(|error| DecodeError::FfmpegUnavailable {
+            reason: format!("failed to run ffmpeg: {error}"),
+        })?;
+    let stdout = child
         .stdout
         .take()
-        .ok_or_else(|| "failed to open ffmpeg stdout".to_string())?;
+        .ok_or_else(|| DecodeError::ffmpeg("failed to open ffmpeg stdout"))?;
 
-    let max_frames = end_frame - start_frame + 1;
-    let mut frames = Vec::new();
-    let mut index = 0usize;
-
-    loop {
-        let mut frame = vec![0u8; frame_size];
-        match stdout.read_exact(&mut frame) {
-            Ok(()) => {
-                if index < max_frames {
-                    frames.push(frame);
-                }
-                index = index.saturating_add(1);
-            }
-            Err(error) => {
-                if error.kind() == io::ErrorKind::UnexpectedEof {
-                    break;
-                }
-                return Err(format!("failed to read ffmpeg output: {error}"));
-            }
-        }
-    }
-
-    let status = child
-        .wait()
-        .map_err(|error| format!("failed to wait on ffmpeg: {error}"))?;
-    if !status.success() {
-        return Err(format!("ffmpeg failed with status: {status}"));
-    }
+    // stderr を別スレッドで読み切らないと、パイプが詰まって ffmpeg が止まる
+    let stderr_reader = child.stderr.take().map(|mut stderr| {
+        thread::spawn(move || {
+            let mut buf = String::new();
+            let _ = stderr.read_to_string(&mut buf);
+            buf
+        })
+    });
 
-    Ok(frames)
+    Ok(RawFrameReader {
+        child,
+        stdout,
+        stderr_reader,
+        frame_size,
+    })
 }
@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+/// Field order of a video stream, from ffprobe's `field_order`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FieldOrder {
+    /// Not reported; individual frames may still be flagged interlaced.
+    #[default]
+    Unknown,
+    Progressive,
+    /// Top field shown first (`tt`, `bt`).
+    TopFirst,
+    /// Bottom field shown first (`bb`, `tb`).
+    BottomFirst,
+}
+
+impl FieldOrder {
+    pub(crate) fn from_probe(value: Option<&str>) -> Self {
+        // tb / bt は符号化順と表示順が逆。表示順で見る
+        match value {
+            Some("progressive") => Self::Progressive,
+            Some("tt" | "bt") => Self::TopFirst,
+            Some("bb" | "tb") => Self::BottomFirst,
+            _ => Self::Unknown,
+        }
+    }
+
+    fn parity(self) -> &'static str {
+        match self {
+            Self::TopFirst => "tff",
+            Self::BottomFirst => "bff",
+            Self::Unknown | Self::Progressive => "auto",
+        }
+    }
+}
+
+/// How interlaced frames are turned into progressive ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Deinterlace {
+    /// Frames are passed through, combed if the source is interlaced.
+    Off,
+    /// `bwdif` on frames flagged interlaced, unless the stream is progressive.
+    #[default]
+    Auto,
+    /// `bwdif` on every frame, for sources flagged wrongly.
+    Bwdif,
+    /// `yadif` on every frame. Cheaper than `bwdif`, a little softer.
+    Yadif,
+    /// `bwdif` outputting one frame per field: frames `2n` and `2n + 1` are the two fields of
+    /// source frame `n`, at twice the frame rate.
+    Double,
+}
+
+impl Deinterlace {
+    /// Output frames per source frame.
+    pub fn fields_per_frame(self) -> u32 {
+        match self {
+            Deinterlace::Double => 2,
+            _ => 1,
+        }
+    }
+
+    /// The deinterlacing filter for a stream with `field_order`, if any.
+    pub fn filter(self, field_order: FieldOrder) -> Option<String> {
+        let parity = field_order.parity();
+        match self {
+            Deinterlace::Off => None,
+            Deinterlace::Auto if field_order == FieldOrder::Progressive => None,
+            Deinterlace::Auto => Some(format!(
+                "bwdif=mode=send_frame:parity={parity}:deint=interlaced"
+            )),
+            Deinterlace::Bwdif => Some(format!("bwdif=mode=send_frame:parity={parity}:deint=all")),
+            Deinterlace::Yadif => Some(format!("yadif=mode=send_frame:parity={parity}:deint=all")),
+            Deinterlace::Double => Some(format!("bwdif=mode=send_field:parity={parity}:deint=all")),
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use std::hash::{Hash, Hasher};
+
+use serde::Deserialize;
+
+use crate::ffmpeg::{
+    StreamInfo, color::ToneMapping, deinterlace::Deinterlace, grade::ColorGrade, key::ChromaKey,
+};
+
+/// How the decoded picture is sized and placed in the output frame.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default, Deserialize)]
+pub struct OutputParams {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub scale: ScaleMode,
+    /// Region of the source (in source pixels) to use before scaling.
+    #[serde(default)]
+    pub crop: Option<CropRect>,
+    #[serde(default)]
+    pub alpha: AlphaMode,
+    /// Curve used for HDR sources.
+    #[serde(default)]
+    pub tonemap: ToneMapping,
+    /// Deinterlacing of interlaced sources. `Double` also changes the frame numbering.
+    #[serde(default)]
+    pub deinterlace: Deinterlace,
+    /// LUT and exposure / contrast / saturation, applied before scaling.
+    #[serde(default)]
+    pub grade: ColorGrade,
+    /// Keys out a background color after grading, so the output has alpha.
+    #[serde(default)]
+    pub key: Option<ChromaKey>,
+    /// Motion-interpolated frames per source frame, for slow motion. 0 and 1 are off.
+    #[serde(default)]
+    pub interpolate: u32,
+    /// Project frame rate the source is resampled to, keeping the source frame closest to
+    /// each project frame (`ConformMode::Nearest`). Frames are then counted in project frames.
+    /// Set by the decoder, not by requests.
+    #[serde(skip)]
+    pub conform_fps: Option<FrameRate>,
+}
+
+/// Frames per second, compared by value so it can be part of a decoder key.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameRate(pub f64);
+
+impl PartialEq for FrameRate {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bits() == other.0.to_bits()
+    }
+}
+
+impl Eq for FrameRate {}
+
+impl Hash for FrameRate {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.to_bits().hash(state);
+    }
+}
+
+/// Where a reader starts on the project frame grid of `OutputParams::conform_fps`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConformStart {
+    /// Time of the first frame the reader decodes, in seconds from the source's first frame.
+    pub first_frame_time: f64,
+    /// The project frame the reader outputs first.
+    pub frame: u32,
+}
+
+/// Most frames `interpolate` makes out of one source frame.
+pub const MAX_INTERPOLATION: u32 = 8;
+
+/// Size and fit of the frames a caller asked for. A clip is decoded at one size that covers
+/// every size asked of it, and each requested size is scaled down from those frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FrameSize {
+    pub width: u32,
+    pub height: u32,
+    pub scale: ScaleMode,
+}
+
+impl FrameSize {
+    /// The smallest uniform scaling of a `width` x `height` picture that this size can be
+    /// scaled down from: one that covers it, or for `Contain` one that fits it.
+    pub fn covering(&self, (width, height): (u32, u32)) -> (u32, u32) {
+        if width == 0 || height == 0 {
+            return (self.width, self.height);
+        }
+        let (x, y) = (
+            self.width as f64 / width as f64,
+            self.height as f64 / height as f64,
+        );
+        let scale = match self.scale {
+            ScaleMode::Contain { .. } => x.min(y),
+            ScaleMode::Stretch | ScaleMode::Cover { .. } => x.max(y),
+        };
+        (
+            ((width as f64 * scale).ceil() as u32).max(1),
+            ((height as f64 * scale).ceil() as u32).max(1),
+        )
+    }
+}
+
+/// Canonical size of sources that don't report theirs.
+const FALLBACK_CANONICAL_SIZE: (u32, u32) = (1920, 1080);
+
+/// How color is stored next to alpha in output frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlphaMode {
+    /// Color independent of alpha, as canvas `ImageData` expects.
+    #[default]
+    Straight,
+    /// Color already multiplied by alpha.
+    Premultiplied,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(tag = "mode", rename_all = "lowercase")]
+pub enum ScaleMode {
+    /// Scale to the output size, ignoring the aspect ratio.
+    #[default]
+    Stretch,
+    /// Fit inside the output size and fill the rest with `background`.
+    Contain {
+        #[serde(default)]
+        background: Background,
+    },
+    /// Fill the output size and cut off what overflows, keeping `anchor` in view.
+    Cover {
+        #[serde(default)]
+        anchor: Anchor,
+    },
+}
+
+/// Letterbox fill: `"transparent"`, `"#rrggbb"` or `"#rrggbbaa"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Background {
+    #[default]
+    Transparent,
+    Rgba(u32),
+}
+
+impl TryFrom<String> for Background {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        if value.eq_ignore_ascii_case("transparent") {
+            return Ok(Background::Transparent);
+        }
+
+        let hex = value.strip_prefix('#').unwrap_or(value);
+        let parsed = u32::from_str_radix(hex, 16).map_err(|_| format!("invalid color: {value}"));
+        match hex.len() {
+            6 => Ok(Background::Rgba((parsed? << 8) | 0xff)),
+            8 => Ok(Background::Rgba(parsed?)),
+            _ => Err(format!("invalid color: {value}")),
+        }
+    }
+}
+
+impl Background {
+    /// The color as straight RGBA bytes.
+    pub fn rgba(self) -> [u8; 4] {
+        match self {
+            Background::Transparent => [0; 4],
+            Background::Rgba(rgba) => rgba.to_be_bytes(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    #[default]
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    /// Top-left corner of the part kept when a picture overflows by `overflow` pixels.
+    pub fn origin(self, overflow: (u32, u32)) -> (u32, u32) {
+        let x = match self {
+            Anchor::TopLeft | Anchor::Left | Anchor::BottomLeft => 0,
+            Anchor::Top | Anchor::Center | Anchor::Bottom => overflow.0 / 2,
+            Anchor::TopRight | Anchor::Right | Anchor::BottomRight => overflow.0,
+        };
+        let y = match self {
+            Anchor::TopLeft | Anchor::Top | Anchor::TopRight => 0,
+            Anchor::Left | Anchor::Center | Anchor::Right => overflow.1 / 2,
+            Anchor::BottomLeft | Anchor::Bottom | Anchor::BottomRight => overflow.1,
+        };
+        (x, y)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl OutputParams {
+    pub fn frame_size(&self) -> usize {
+        (self.width as usize)
+            .saturating_mul(self.height as usize)
+            .saturating_mul(4)
+    }
+
+    /// Split into the parameters frames are decoded with, which leave the size to the source
+    /// (see `at_canonical_size`), and the size and fit the frames are then scaled to.
+    pub fn split_size(&self) -> (OutputParams, FrameSize) {
+        let size = FrameSize {
+            width: self.width,
+            height: self.height,
+            scale: self.scale,
+        };
+        let canonical = OutputParams {
+            width: 0,
+            height: 0,
+            scale: ScaleMode::Stretch,
+            ..self.clone()
+        };
+        (canonical, size)
+    }
+
+    /// Fill in the size left open by `split_size`: the upright (and cropped) picture of
+    /// `source` at its own size. A set size is kept.
+    pub fn at_canonical_size(&self, source: StreamInfo) -> OutputParams {
+        if self.width > 0 && self.height > 0 {
+            return self.clone();
+        }
+
+        let (width, height) = match self.crop {
+            Some(crop) => (crop.width.max(1), crop.height.max(1)),
+            None => source.display_size(),
+        };
+        let (width, height) = if width == 0 || height == 0 {
+            FALLBACK_CANONICAL_SIZE
+        } else {
+            (width, height)
+        };
+        OutputParams {
+            width,
+            height,
+            ..self.clone()
+        }
+    }
+
+    /// Output frames per source frame: fields when deinterlacing to field rate, times the
+    /// motion interpolation factor.
+    pub fn frames_per_source_frame(&self) -> u32 {
+        self.deinterlace.fields_per_frame() * self.interpolate.clamp(1, MAX_INTERPOLATION)
+    }
+
+    /// Filters that turn a decoded source frame into an output frame, in order.
+    ///
+    /// Interlaced frames are deinterlaced while the fields are still in coded lines, and
+    /// motion interpolated at the deinterlaced rate. The picture is then turned upright by the
+    /// stream's display matrix, then converted to full-range BT.709 RGB from the source's
+    /// color tags (tone mapped if HDR), graded and keyed. A picture with alpha (from the
+    /// source or the key) is scaled premultiplied, so transparent pixels don't bleed their
+    /// color into the edges, and converted back if straight alpha was asked for.
+    ///
+    /// The picture is stretched to `width` x `height`; `scale` is applied afterwards, when
+    /// decoded frames are resized to the requested size (see `split_size`).
+    pub fn filters(&self, source: StreamInfo) -> Vec<String> {
+        self.filters_from(source, ConformStart::default())
+    }
+
+    /// `filters` for a reader starting at `start`. With `conform_fps`, frames no project frame
+    /// shows are dropped right after deinterlacing, before anything else is done to them.
+    pub fn filters_from(&self, source: StreamInfo, start: ConformStart) -> Vec<String> {
+        let (width, height) = (self.width, self.height);
+        let source_alpha = source.alpha.has_alpha;
+        let mut filters = Vec::new();
+        filters.extend(self.deinterlace.filter(source.field_order));
+        if let Some(filter) = self.interpolation_filter(source) {
+            filters.push(filter);
+        }
+        filters.extend(self.conform_filters(start));
+        filters.extend(
+            source
+                .orientation
+                .filters()
+                .iter()
+                .map(|filter| filter.to_string()),
+        );
+        filters.extend(
+            source
+                .color
+                .conversion(source.height, self.tonemap)
+                .filters(source_alpha),
+        );
+        filters.extend(self.grade.filters());
+        if let Some(key) = &self.key {
+            filters.extend(key.filters());
+        }
+
+        let alpha = source_alpha || self.key.is_some();
+        if alpha {
+            filters.push("format=gbrap".to_string());
+            filters.push("premultiply=inplace=1".to_string());
+        }
+
+        if let Some(crop) = self.crop {
+            filters.push(format!(
+                "crop={}:{}:{}:{}",
+                crop.width.max(1),
+                crop.height.max(1),
+                crop.x,
+                crop.y
+            ));
+        }
+
+        filters.push(format!("scale={width}x{height}"));
+
+        if alpha && self.alpha == AlphaMode::Straight {
+            filters.push("format=gbrap".to_string());
+            filters.push("unpremultiply=inplace=1".to_string());
+        }
+
+        filters.push("setsar=1".to_string());
+        filters
+    }
+
+    /// `setpts` and `fps` keeping the frame closest to each project frame from `start.frame`.
+    ///
+    /// Each frame is timed at the midpoint between it and the frame before, from where on it
+    /// is the closest, counted from the source's first frame. `fps` rounding up then shows at
+    /// each project frame the last frame timed at or before it. The reader's first frame has
+    /// no frame before it and keeps its own time; project frames before it show it too.
+    fn conform_filters(&self, start: ConformStart) -> Vec<String> {
+        let Some(FrameRate(fps)) = self.conform_fps else {
+            return Vec::new();
+        };
+        vec![
+            format!(
+                "setpts='if(isnan(PREV_INPTS),PTS,(PTS+PREV_INPTS)/2)-STARTPTS+{}/TB'",
+                start.first_frame_time
+            ),
+            format!(
+                "fps=fps={fps}:start_time={}:round=up",
+                start.frame as f64 / fps
+            ),
+        ]
+    }
+
+    /// `minterpolate` to `frames_per_source_frame` times the source rate. Skipped when the
+    /// source rate is unknown.
+    fn interpolation_filter(&self, source: StreamInfo) -> Option<String> {
+        if self.interpolate.clamp(1, MAX_INTERPOLATION) == 1 {
+            return None;
+        }
+        let (num, den) = source.frame_rate?;
+        let num = u64::from(num) * u64::from(self.frames_per_source_frame());
+        Some(format!(
+            "minterpolate=fps={num}/{den}:mi_mode=mci:mc_mode=aobmc:me_mode=bidir:vsbmc=1"
+        ))
+    }
+}
@@ -0,0 +1,148 @@
+use std::hash::{Hash, Hasher};
+
+use serde::Deserialize;
+
+/// Color correction applied to the RGB picture before it is scaled, so the studio preview
+/// and the render get the same pixels.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ColorGrade {
+    /// `.cube` 3D LUT, e.g. to bring log footage to Rec.709. Applied before the adjustments.
+    #[serde(default)]
+    pub lut: Option<String>,
+    /// In stops: `1.0` doubles the values, `-1.0` halves them.
+    #[serde(default)]
+    pub exposure: f64,
+    /// Around mid-gray; `1.0` is unchanged, `0.0` is flat gray.
+    #[serde(default = "unchanged")]
+    pub contrast: f64,
+    /// `1.0` is unchanged, `0.0` is grayscale.
+    #[serde(default = "unchanged")]
+    pub saturation: f64,
+}
+
+fn unchanged() -> f64 {
+    1.0
+}
+
+impl Default for ColorGrade {
+    fn default() -> Self {
+        Self {
+            lut: None,
+            exposure: 0.0,
+            contrast: 1.0,
+            saturation: 1.0,
+        }
+    }
+}
+
+impl PartialEq for ColorGrade {
+    fn eq(&self, other: &Self) -> bool {
+        self.lut == other.lut
+            && self.exposure.to_bits() == other.exposure.to_bits()
+            && self.contrast.to_bits() == other.contrast.to_bits()
+            && self.saturation.to_bits() == other.saturation.to_bits()
+    }
+}
+
+impl Eq for ColorGrade {}
+
+impl Hash for ColorGrade {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.lut.hash(state);
+        self.exposure.to_bits().hash(state);
+        self.contrast.to_bits().hash(state);
+        self.saturation.to_bits().hash(state);
+    }
+}
+
+/// Rec.709 luma weights, which saturation keeps constant.
+const LUMA: [f64; 3] = [0.2126, 0.7152, 0.0722];
+
+impl ColorGrade {
+    /// Filters applying the grade to RGB input. `lut` must already be a resolved path.
+    pub fn filters(&self) -> Vec<String> {
+        let mut filters = Vec::new();
+        if let Some(lut) = &self.lut {
+            filters.push(format!(
+                "lut3d=file={}:interp=tetrahedral",
+                escape_filter_value(lut)
+            ));
+        }
+
+        let exposure = if self.exposure.is_finite() {
+            self.exposure
+        } else {
+            0.0
+        };
+        if exposure != 0.0 {
+            let gain = 2f64.powf(exposure);
+            filters.push(format!(
+                "colorchannelmixer=rr={gain:.6}:gg={gain:.6}:bb={gain:.6}"
+            ));
+        }
+
+        // 中間グレーを中心に、入力 (強める) か出力 (弱める) のレンジを狭める
+        let contrast = if self.contrast.is_finite() {
+            self.contrast.max(0.0)
+        } else {
+            1.0
+        };
+        if contrast > 1.0 {
+            let (low, high) = (0.5 - 0.5 / contrast, 0.5 + 0.5 / contrast);
+            filters.push(format!(
+                "colorlevels=rimin={low:.6}:gimin={low:.6}:bimin={low:.6}:rimax={high:.6}:gimax={high:.6}:bimax={high:.6}"
+            ));
+        } else if contrast < 1.0 {
+            let (low, high) = (0.5 - 0.5 * contrast, 0.5 + 0.5 * contrast);
+            filters.push(format!(
+                "colorlevels=romin={low:.6}:gomin={low:.6}:bomin={low:.6}:romax={high:.6}:gomax={high:.6}:bomax={high:.6}"
+            ));
+        }
+
+        let saturation = if self.saturation.is_finite() {
+            self.saturation.max(0.0)
+        } else {
+            1.0
+        };
+        if saturation != 1.0 {
+            let mix = |out: usize, input: usize| {
+                let identity = if out == input { saturation } else { 0.0 };
+                LUMA[input] * (1.0 - saturation) + identity
+            };
+            filters.push(format!(
+                "colorchannelmixer=rr={:.6}:rg={:.6}:rb={:.6}:gr={:.6}:gg={:.6}:gb={:.6}:br={:.6}:bg={:.6}:bb={:.6}",
+                mix(0, 0),
+                mix(0, 1),
+                mix(0, 2),
+                mix(1, 0),
+                mix(1, 1),
+                mix(1, 2),
+                mix(2, 0),
+                mix(2, 1),
+                mix(2, 2),
+            ));
+        }
+        filters
+    }
+}
+
+/// Escape a value for a filter option inside a filter graph: once for the option parser
+/// (`'`, `:`, `\`) and once for the graph parser. Windows separators become `/`.
+fn escape_filter_value(value: &str) -> String {
+    let mut option = String::new();
+    for c in value.replace('\\', "/").chars() {
+        if matches!(c, '\'' | ':' | '\\') {
+            option.push('\\');
+        }
+        option.push(c);
+    }
+
+    let mut graph = String::new();
+    for c in option.chars() {
+        if matches!(c, '\\' | '\'' | '[' | ']' | ',' | ';') {
+            graph.push('\\');
+        }
+        graph.push(c);
+    }
+    graph
+}
@@ -0,0 +1,281 @@
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, LazyLock, Mutex},
+    time::SystemTime,
+};
+
+use crate::ffmpeg::{
+    STREAM_INFO_ENTRIES, StreamInfo, run_ffprobe,
+    stream::{MediaKind, stream_specifier},
+};
+
+/// Frame and keyframe index of one video stream, built from ffprobe packet data.
+///
+/// Frame `n` here is the `n`-th frame ffmpeg outputs with `-vsync 0`: packets are put in
+/// presentation order (B-frames are stored out of order) and packets an edit list discards
+/// are left out. So the index is correct for variable-frame-rate sources where `n / fps` is not.
+#[derive(Debug)]
+pub struct MediaIndex {
+    /// Presentation time of each frame, in stream seconds. Sorted ascending.
+    pts: Vec<f64>,
+    /// Keyframes in presentation order.
+    keyframes: Vec<Keyframe>,
+    /// The container start time. Input `-ss` positions are relative to it.
+    start_time: f64,
+    /// How long the last frame is shown, if the container says.
+    last_duration: Option<f64>,
+    stream: StreamInfo,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Keyframe {
+    pub frame: u32,
+}
+
+impl MediaIndex {
+    pub fn frame_count(&self) -> usize {
+        self.pts.len()
+    }
+
+    pub fn stream(&self) -> StreamInfo {
+        self.stream
+    }
+
+    /// Presentation time of `frame`, in seconds from the first frame.
+    pub fn time_of(&self, frame: u32) -> Option<f64> {
+        let first = self.pts.first()?;
+        Some(self.pts.get(frame as usize)? - first)
+    }
+
+    /// Input seek position that makes `frame` the first frame ffmpeg outputs: halfway between
+    /// it and the previous frame, so rounding never drops or repeats a frame.
+    pub fn seek_time(&self, frame: u32) -> Option<f64> {
+        let pts = *self.pts.get(frame as usize)?;
+        let target = match frame.checked_sub(1) {
+            Some(prev) => (self.pts[prev as usize] + pts) / 2.0,
+            None => return Some(0.0),
+        };
+        Some((target - self.start_time).max(0.0))
+    }
+
+    /// The last keyframe at or before `frame`, where decoding `frame` has to start.
+    pub fn keyframe_before(&self, frame: u32) -> Option<Keyframe> {
+        let after = self
+            .keyframes
+            .partition_point(|keyframe| keyframe.frame <= frame);
+        after.checked_sub(1).map(|i| self.keyframes[i])
+    }
+
+    /// How long `frame` is shown, in seconds: the gap to the next frame, or the packet duration
+    /// for the last one.
+    pub fn frame_duration(&self, frame: u32) -> Option<f64> {
+        let frame = frame as usize;
+        match self.pts.get(frame + 1) {
+            Some(next) => Some(next - self.pts.get(frame)?),
+            None => self.last_duration.filter(|_| frame + 1 == self.pts.len()),
+        }
+    }
+
+    /// How long each frame is shown, in seconds: the gap to the next frame, and the packet
+    /// duration for the last one. Animated images use this for their per-frame delays.
+    pub fn frame_delays(&self) -> Vec<f64> {
+        let mut delays = self
+            .pts
+            .windows(2)
+            .map(|pair| pair[1] - pair[0])
+            .collect::<Vec<_>>();
+        if !self.pts.is_empty() {
+            delays.push(self.last_duration.unwrap_or(0.0));
+        }
+        delays
+    }
+
+    /// The frame on screen at `seconds`: the last frame whose presentation time is not after it.
+    pub fn frame_at(&self, seconds: f64) -> Option<u32> {
+        let first = *self.pts.first()?;
+        // ごく僅かな誤差で直前のフレームに落ちないように許容する
+        const EPSILON: f64 = 1e-6;
+        let after = self
+            .pts
+            .partition_point(|pts| *pts - first <= seconds + EPSILON);
+        Some(after.saturating_sub(1) as u32)
+    }
+}
+
+/// Size and modification time of a file, to notice when it is replaced or re-rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    len: u64,
+    modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    fn of(path: &str) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+struct CachedIndex {
+    stamp: Option<FileStamp>,
+    index: Arc<MediaIndex>,
+}
+
+static INDEXES: LazyLock<Mutex<HashMap<(String, u32), CachedIndex>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Return the index of the `stream`-th video stream of `path`, probing it with ffprobe on
+/// first use and again whenever the file has changed since.
+pub fn media_index(path: &str, stream: u32) -> Result<Arc<MediaIndex>, String> {
+    let key = (path.to_string(), stream);
+    let stamp = FileStamp::of(path);
+    if let Some(cached) = INDEXES.lock().unwrap().get(&key)
+        && cached.stamp == stamp
+    {
+        return Ok(cached.index.clone());
+    }
+
+    let index = Arc::new(build_index(path, stream)?);
+    INDEXES.lock().unwrap().insert(
+        key,
+        CachedIndex {
+            stamp,
+            index: index.clone(),
+        },
+    );
+    Ok(index)
+}
+
+pub fn clear_media_indexes() {
+    INDEXES.lock().unwrap().clear();
+}
+
+fn build_index(path: &str, stream: u32) -> Result<MediaIndex, String> {
+    let output = run_ffprobe(
+        path,
+        Some(&stream_specifier(MediaKind::Video, stream)),
+        &format!(
+            "packet=pts_time,dts_time,duration_time,flags:format=start_time:{STREAM_INFO_ENTRIES}"
+        ),
+    )?;
+    let stream = output
+        .streams
+        .as_ref()
+        .and_then(|streams| streams.first())
+        .map(|info| StreamInfo::of(path, stream, info))
+        .unwrap_or(StreamInfo {
+            index: stream,
+            ..Default::default()
+        });
+
+    let mut packets = output
+        .packets
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|packet| {
+            let flags = packet.flags.as_deref().unwrap_or_default();
+            // 編集リストで捨てられるパケットはデコーダから出てこない
+            if flags.contains('D') {
+                return None;
+            }
+            let pts = parse_time(packet.pts_time.as_deref())
+                .or_else(|| parse_time(packet.dts_time.as_deref()))?;
+            let duration = parse_time(packet.duration_time.as_deref());
+            Some((pts, flags.contains('K'), duration))
+        })
+        .collect::<Vec<_>>();
+    packets.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let pts = packets.iter().map(|(pts, _, _)| *pts).collect();
+    let last_duration = packets.last().and_then(|(_, _, duration)| *duration);
+    let keyframes = packets
+        .iter()
+        .enumerate()
+        .filter(|(_, (_, key, _))| *key)
+        .map(|(frame, _)| Keyframe {
+            frame: frame as u32,
+        })
+        .collect();
+    let start_time = output
+        .format
+        .and_then(|format| parse_time(format.start_time.as_deref()))
+        .unwrap_or(0.0);
+
+    Ok(MediaIndex {
+        pts,
+        keyframes,
+        start_time,
+        last_duration,
+        stream,
+    })
+}
+
+fn parse_time(value: Option<&str>) -> Option<f64> {
+    let value = value?.trim();
+    if value.is_empty() || value == "N/A" {
+        return None;
+    }
+    value.parse::<f64>().ok().filter(|time| time.is_finite())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frames at `pts`, every third one a keyframe.
+    fn index(pts: &[f64], start_time: f64) -> MediaIndex {
+        MediaIndex {
+            pts: pts.to_vec(),
+            keyframes: (0..pts.len() as u32)
+                .step_by(3)
+                .map(|frame| Keyframe { frame })
+                .collect(),
+            start_time,
+            last_duration: Some(0.04),
+            stream: StreamInfo::default(),
+        }
+    }
+
+    #[test]
+    fn seek_time_is_halfway_to_the_previous_frame() {
+        let index = index(&[0.0, 0.04, 0.08, 0.12], 0.0);
+        assert_eq!(index.seek_time(0), Some(0.0));
+        assert!((index.seek_time(1).unwrap() - 0.02).abs() < 1e-9);
+        assert!((index.seek_time(3).unwrap() - 0.10).abs() < 1e-9);
+        assert_eq!(index.seek_time(4), None);
+    }
+
+    #[test]
+    fn seek_time_is_relative_to_the_container_start() {
+        // 1秒から始まるストリームの -ss は開始時刻からの位置
+        let index = index(&[1.0, 1.5, 2.0], 1.0);
+        assert!((index.seek_time(1).unwrap() - 0.25).abs() < 1e-9);
+        assert!((index.seek_time(2).unwrap() - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn seek_time_never_goes_before_the_start() {
+        // 編集リストで開始時刻より前に出るフレーム
+        let index = index(&[0.9, 0.95, 1.0], 1.0);
+        assert_eq!(index.seek_time(1), Some(0.0));
+    }
+
+    #[test]
+    fn variable_frame_rate_seeks_by_presentation_time() {
+        let index = index(&[0.0, 0.1, 0.15, 0.5], 0.0);
+        assert!((index.seek_time(3).unwrap() - 0.325).abs() < 1e-9);
+        assert_eq!(index.frame_at(0.3), Some(2));
+        assert_eq!(
+            index.keyframe_before(2).map(|keyframe| keyframe.frame),
+            Some(0)
+        );
+        assert_eq!(
+            index.keyframe_before(3).map(|keyframe| keyframe.frame),
+            Some(3)
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use std::hash::{Hash, Hasher};
+
+use serde::Deserialize;
+
+/// Keys out a background color, giving the output alpha. Applied after grading, so the key
+/// color is picked from the picture as it is shown.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct ChromaKey {
+    /// `"#rrggbb"`.
+    pub color: KeyColor,
+    /// How far from `color` a pixel is still keyed, 0.01 to 1.
+    #[serde(default = "default_similarity")]
+    pub similarity: f64,
+    /// Softness of the edge, 0 (hard) to 1.
+    #[serde(default)]
+    pub blend: f64,
+    /// How much of the key color's cast is removed from what is left, 0 (none) to 1.
+    /// Only green and blue keys have spill suppression.
+    #[serde(default)]
+    pub spill: f64,
+    #[serde(default)]
+    pub method: KeyMethod,
+}
+
+fn default_similarity() -> f64 {
+    0.1
+}
+
+/// How the distance to the key color is measured.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyMethod {
+    /// Chroma only (`chromakey`), so shadows and folds in the screen are keyed too.
+    #[default]
+    Chroma,
+    /// RGB distance (`colorkey`), for backgrounds that are not a saturated color.
+    Color,
+}
+
+/// An opaque color, `0xRRGGBB`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
+pub struct KeyColor(u32);
+
+impl TryFrom<String> for KeyColor {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let value = value.trim();
+        let hex = value.strip_prefix('#').unwrap_or(value);
+        match hex.len() {
+            6 => u32::from_str_radix(hex, 16)
+                .map(KeyColor)
+                .map_err(|_| format!("invalid key color: {value}")),
+            _ => Err(format!("invalid key color: {value}")),
+        }
+    }
+}
+
+impl KeyColor {
+    fn channels(self) -> [u32; 3] {
+        [(self.0 >> 16) & 0xff, (self.0 >> 8) & 0xff, self.0 & 0xff]
+    }
+
+    /// `despill` type for the dominant channel, if it is green or blue.
+    fn spill_type(self) -> Option<&'static str> {
+        let [r, g, b] = self.channels();
+        if g > r && g >= b {
+            Some("green")
+        } else if b > r && b > g {
+            Some("blue")
+        } else {
+            None
+        }
+    }
+}
+
+impl PartialEq for ChromaKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.color == other.color
+            && self.similarity.to_bits() == other.similarity.to_bits()
+            && self.blend.to_bits() == other.blend.to_bits()
+            && self.spill.to_bits() == other.spill.to_bits()
+            && self.method == other.method
+    }
+}
+
+impl Eq for ChromaKey {}
+
+impl Hash for ChromaKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.color.hash(state);
+        self.similarity.to_bits().hash(state);
+        self.blend.to_bits().hash(state);
+        self.spill.to_bits().hash(state);
+        self.method.hash(state);
+    }
+}
+
+impl ChromaKey {
+    /// Filters keying full-range RGB input. The output is `gbrap` with straight alpha.
+    pub fn filters(&self) -> Vec<String> {
+        let clamp = |value: f64, min: f64, default: f64| {
+            if value.is_finite() {
+                value.clamp(min, 1.0)
+            } else {
+                default
+            }
+        };
+        let similarity = clamp(self.similarity, 0.01, default_similarity());
+        let blend = clamp(self.blend, 0.0, 0.0);
+        let spill = clamp(self.spill, 0.0, 0.0);
+        let color = format!("0x{:06x}", self.color.0);
+
+        let mut filters = match self.method {
+            // chromakey はキー色を BT.601 リミテッドで YUV にするので、同じ変換で合わせる
+            KeyMethod::Chroma => vec![
+                "scale=out_color_matrix=bt601:out_range=tv".to_string(),
+                "format=yuva444p".to_string(),
+                format!("chromakey=color={color}:similarity={similarity:.4}:blend={blend:.4}"),
+                "scale=in_color_matrix=bt601:in_range=tv".to_string(),
+                "format=gbrap".to_string(),
+            ],
+            KeyMethod::Color => vec![
+                "format=gbrap".to_string(),
+                format!("colorkey=color={color}:similarity={similarity:.4}:blend={blend:.4}"),
+            ],
+        };
+
+        if let Some(kind) = self.color.spill_type().filter(|_| spill > 0.0) {
+            let despill = format!("despill=type={kind}");
+            if spill >= 1.0 {
+                filters.push(despill);
+            } else {
+                // 元の画像と混ぜて強さを調整する
+                filters.push(format!(
+                    "split[key_in][key_spill];[key_spill]{despill}[key_despilled];[key_in][key_despilled]blend=all_mode=normal:all_opacity={:.4}",
+                    1.0 - spill
+                ));
+            }
+        }
+        filters
+    }
+}
@@ -0,0 +1,267 @@
+use std::{fmt, sync::OnceLock};
+
+use ffmpeg_next::{self as av, Packet, codec, filter, format, frame, media};
+
+use crate::error::DecodeError;
+use crate::ffmpeg::StreamInfo;
+use crate::ffmpeg::filter::{ConformStart, OutputParams};
+use crate::ffmpeg::reader::FrameReader;
+
+static INIT: OnceLock<Result<(), String>> = OnceLock::new();
+
+fn init() -> Result<(), DecodeError> {
+    INIT.get_or_init(|| av::init().map_err(|e| e.to_string()))
+        .clone()
+        .map_err(|reason| DecodeError::FfmpegUnavailable { reason })
+}
+
+fn av_error(reason: &'static str) -> impl Fn(av::Error) -> DecodeError {
+    move |e| DecodeError::ffmpeg(format!("{reason}: {e}"))
+}
+
+fn is_again(e: &av::Error) -> bool {
+    matches!(e, av::Error::Other { errno } if *errno == av::error::EAGAIN)
+}
+
+/// Decodes in-process with libavformat/libavcodec and runs the same filter chain as the
+/// CLI path through libavfilter, so both produce identical frames.
+pub(crate) struct LibavReader {
+    path: String,
+    input: format::context::Input,
+    stream_index: usize,
+    time_base: av::Rational,
+    decoder: codec::decoder::Video,
+    output: OutputParams,
+    conform: ConformStart,
+    stream: StreamInfo,
+    /// Built from the first decoded frame, whose format and size are authoritative.
+    graph: Option<filter::Graph>,
+    /// Frames before this time (seconds) are decoded but not output, like `-ss` on the CLI.
+    skip_before: Option<f64>,
+    decoder_eof: bool,
+    graph_flushed: bool,
+}
+
+impl fmt::Debug for LibavReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LibavReader")
+            .field("path", &self.path)
+            .field("stream_index", &self.stream_index)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LibavReader {
+    pub(crate) fn open(
+        path: &str,
+        seek_seconds: Option<f64>,
+        output: &OutputParams,
+        conform: ConformStart,
+        stream: StreamInfo,
+    ) -> Result<Self, DecodeError> {
+        init()?;
+        if output.frame_size() == 0 {
+            return Err(DecodeError::ffmpeg("invalid output size"));
+        }
+
+        let mut input = format::input(path).map_err(av_error("failed to open input"))?;
+        let video_stream = stream.index as usize;
+        let (stream_index, time_base, parameters) = {
+            let stream = input
+                .streams()
+                .filter(|stream| stream.parameters().medium() == media::Type::Video)
+                .nth(video_stream)
+                .ok_or_else(|| DecodeError::NoVideoStream {
+                    path: path.to_string(),
+                })?;
+            (stream.index(), stream.time_base(), stream.parameters())
+        };
+
+        let mut context = codec::context::Context::from_parameters(parameters)
+            .map_err(av_error("failed to read codec parameters"))?;
+        context.set_threading(codec::threading::Config {
+            kind: codec::threading::Type::Frame,
+            ..Default::default()
+        });
+        let decoder = match stream.alpha.decoder.and_then(codec::decoder::find_by_name) {
+            Some(codec) => context
+                .decoder()
+                .open_as(codec)
+                .and_then(|opened| opened.video()),
+            None => context.decoder().video(),
+        }
+        .map_err(av_error("failed to open decoder"))?;
+
+        // CLI の -ss と同じく、コンテナの開始時刻からの相対位置としてシークする
+        let skip_before = match seek_seconds.filter(|seconds| *seconds > 0.0) {
+            Some(seconds) => {
+                let start = unsafe { (*input.as_ptr()).start_time };
+                let start = if start == av::ffi::AV_NOPTS_VALUE {
+                    0.0
+                } else {
+                    start as f64 / f64::from(av::ffi::AV_TIME_BASE)
+                };
+                let target = start + seconds;
+                let ts = (target * f64::from(av::ffi::AV_TIME_BASE)) as i64;
+                input.seek(ts, ..ts).map_err(av_error("failed to seek"))?;
+                Some(target)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            path: path.to_string(),
+            input,
+            stream_index,
+            time_base,
+            decoder,
+            output: output.clone(),
+            conform,
+            stream,
+            graph: None,
+            skip_before,
+            decoder_eof: false,
+            graph_flushed: false,
+        })
+    }
+
+    fn build_graph(&self, first: &frame::Video) -> Result<filter::Graph, DecodeError> {
+        let mut graph = filter::Graph::new();
+
+        let aspect = first.aspect_ratio();
+        let aspect = if aspect.numerator() > 0 && aspect.denominator() > 0 {
+            aspect
+        } else {
+            av::Rational::new(1, 1)
+        };
+        let args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base={}:pixel_aspect={}",
+            first.width(),
+            first.height(),
+            av::ffi::AVPixelFormat::from(first.format()) as i32,
+            self.time_base,
+            aspect
+        );
+
+        let buffer =
+            filter::find("buffer").ok_or_else(|| DecodeError::ffmpeg("no buffer filter"))?;
+        let sink = filter::find("buffersink")
+            .ok_or_else(|| DecodeError::ffmpeg("no buffersink filter"))?;
+        graph
+            .add(&buffer, "in", &args)
+            .map_err(av_error("failed to create filter source"))?;
+        graph
+            .add(&sink, "out", "")
+            .map_err(av_error("failed to create filter sink"))?;
+
+        let mut filters = self.output.filters_from(self.stream, self.conform);
+        filters.push("format=rgba".to_string());
+        graph
+            .output("in", 0)
+            .and_then(|parser| parser.input("out", 0))
+            .and_then(|parser| parser.parse(&filters.join(",")))
+            .map_err(av_error("failed to parse filters"))?;
+        graph
+            .validate()
+            .map_err(av_error("failed to configure filters"))?;
+
+        Ok(graph)
+    }
+
+    fn push_decoded(&mut self, decoded: &frame::Video) -> Result<(), DecodeError> {
+        if let (Some(skip_before), Some(pts)) = (self.skip_before, decoded.timestamp()) {
+            let seconds = pts as f64 * f64::from(self.time_base);
+            if seconds < skip_before {
+                return Ok(());
+            }
+        }
+
+        if self.graph.is_none() {
+            self.graph = Some(self.build_graph(decoded)?);
+        }
+        let graph = self.graph.as_mut().unwrap();
+        graph
+            .get("in")
+            .unwrap()
+            .source()
+            .add(decoded)
+            .map_err(av_error("failed to filter frame"))
+    }
+
+    fn copy_rgba(&self, filtered: &frame::Video) -> Result<Vec<u8>, DecodeError> {
+        if filtered.width() != self.output.width || filtered.height() != self.output.height {
+            return Err(DecodeError::ffmpeg("unexpected filtered frame size"));
+        }
+
+        let row = self.output.width as usize * 4;
+        let stride = filtered.stride(0);
+        let data = filtered.data(0);
+        let mut rgba = Vec::with_capacity(self.output.frame_size());
+        for y in 0..self.output.height as usize {
+            rgba.extend_from_slice(&data[y * stride..y * stride + row]);
+        }
+        Ok(rgba)
+    }
+}
+
+impl FrameReader for LibavReader {
+    fn read_frame(&mut self) -> Result<Option<Vec<u8>>, DecodeError> {
+        loop {
+            // フィルタに溜まっているフレームを先に取り出す
+            if let Some(graph) = self.graph.as_mut() {
+                let mut filtered = frame::Video::empty();
+                match graph.get("out").unwrap().sink().frame(&mut filtered) {
+                    Ok(()) => return self.copy_rgba(&filtered).map(Some),
+                    Err(av::Error::Eof) => return Ok(None),
+                    Err(e) if is_again(&e) => {}
+                    Err(e) => return Err(av_error("failed to filter frame")(e)),
+                }
+            }
+
+            let mut decoded = frame::Video::empty();
+            match self.decoder.receive_frame(&mut decoded) {
+                Ok(()) => {
+                    self.push_decoded(&decoded)?;
+                    continue;
+                }
+                Err(av::Error::Eof) => {
+                    let Some(graph) = self.graph.as_mut() else {
+                        return Ok(None);
+                    };
+                    if self.graph_flushed {
+                        return Ok(None);
+                    }
+                    graph
+                        .get("in")
+                        .unwrap()
+                        .source()
+                        .flush()
+                        .map_err(av_error("failed to flush filters"))?;
+                    self.graph_flushed = true;
+                    continue;
+                }
+                Err(e) if is_again(&e) => {}
+                Err(e) => return Err(av_error("failed to decode frame")(e)),
+            }
+
+            if self.decoder_eof {
+                return Ok(None);
+            }
+            let mut packet = Packet::empty();
+            match packet.read(&mut self.input) {
+                Ok(()) if packet.stream() == self.stream_index => self
+                    .decoder
+                    .send_packet(&packet)
+                    .map_err(av_error("failed to decode packet"))?,
+                Ok(()) => {}
+                Err(av::Error::Eof) => {
+                    self.decoder
+                        .send_eof()
+                        .map_err(av_error("failed to flush decoder"))?;
+                    self.decoder_eof = true;
+                }
+                Err(e) => return Err(av_error("failed to read packet")(e)),
+            }
+        }
+    }
+}
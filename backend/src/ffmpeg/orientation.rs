@@ -0,0 +1,77 @@
+/// How a decoded picture has to be turned for display, from the stream's display matrix
+/// (or the older `rotate` tag).
+///
+/// Decoding uses `-noautorotate` and applies these filters itself, so the CLI and libav paths
+/// agree and crop rectangles are in displayed coordinates.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Orientation {
+    /// Clockwise rotation for display: 0, 90, 180 or 270.
+    pub rotation: u32,
+    filters: &'static [&'static str],
+}
+
+impl Orientation {
+    /// From a 3x3 display matrix (16.16 fixed point, row-major), the way ffmpeg's own
+    /// autorotate reads it. Angles that are not a multiple of 90 snap to the nearest one.
+    pub(crate) fn from_matrix(matrix: [i32; 9]) -> Self {
+        let m = matrix.map(f64::from);
+        let (scale_x, scale_y) = (m[0].hypot(m[3]), m[1].hypot(m[4]));
+        if scale_x == 0.0 || scale_y == 0.0 {
+            return Self::default();
+        }
+        let theta = (m[1] / scale_y).atan2(m[0] / scale_x).to_degrees();
+
+        let (rotation, filters): (u32, &'static [&'static str]) = match normalize(theta) {
+            90 if matrix[3] > 0 => (90, &["transpose=cclock_flip"]),
+            90 => (90, &["transpose=clock"]),
+            180 => match (matrix[0] < 0, matrix[4] < 0) {
+                (true, true) => (180, &["hflip", "vflip"]),
+                (true, false) => (180, &["hflip"]),
+                (false, true) => (180, &["vflip"]),
+                (false, false) => (180, &[]),
+            },
+            270 if matrix[3] < 0 => (270, &["transpose=clock_flip"]),
+            270 => (270, &["transpose=cclock"]),
+            _ if matrix[4] < 0 => (0, &["vflip"]),
+            _ => (0, &[]),
+        };
+        Self { rotation, filters }
+    }
+
+    /// From a counterclockwise angle in degrees, as ffprobe reports `rotation`.
+    pub(crate) fn from_rotation(degrees: f64) -> Self {
+        let (rotation, filters): (u32, &'static [&'static str]) = match normalize(-degrees) {
+            90 => (90, &["transpose=clock"]),
+            180 => (180, &["hflip", "vflip"]),
+            270 => (270, &["transpose=cclock"]),
+            _ => (0, &[]),
+        };
+        Self { rotation, filters }
+    }
+
+    /// Whether width and height swap for display.
+    pub fn swaps_dimensions(self) -> bool {
+        self.rotation % 180 == 90
+    }
+
+    pub fn filters(self) -> &'static [&'static str] {
+        self.filters
+    }
+}
+
+/// Round a clockwise angle to 0, 90, 180 or 270.
+fn normalize(degrees: f64) -> u32 {
+    let quarter = (degrees / 90.0).round() as i64;
+    (quarter.rem_euclid(4) * 90) as u32
+}
+
+/// Parse ffprobe's `displaymatrix` text: three lines of `<offset>: a b c`.
+pub(crate) fn parse_display_matrix(text: &str) -> Option<[i32; 9]> {
+    let values = text
+        .lines()
+        .filter_map(|line| line.split_once(':').map(|(_, values)| values))
+        .flat_map(str::split_whitespace)
+        .map(|value| value.parse::<i32>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    values.try_into().ok()
+}
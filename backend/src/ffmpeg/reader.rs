@@ -0,0 +1,71 @@
+use std::fmt;
+
+#[cfg(feature = "libav")]
+use tracing::warn;
+
+use crate::error::DecodeError;
+use crate::ffmpeg::StreamInfo;
+use crate::ffmpeg::command::spawn_rgba_reader;
+use crate::ffmpeg::filter::{ConformStart, OutputParams};
+
+/// A source of output-sized RGBA frames, read in order.
+pub(crate) trait FrameReader: fmt::Debug + Send {
+    /// Read the next frame. Returns `None` at the end of the stream.
+    fn read_frame(&mut self) -> Result<Option<Vec<u8>>, DecodeError>;
+}
+
+/// Start reading `path` from `seek_seconds` (relative to the container start, like `-ss`).
+/// `conform` places the first frame on the project frame grid when resampling.
+///
+/// With the `libav` feature this decodes in-process and falls back to the ffmpeg CLI if the
+/// libraries can't open the file or decode its first frame; otherwise it always uses the CLI. Sources with alpha
+/// are never hardware decoded, since hardware decoders drop the alpha plane.
+pub(crate) fn open_frame_reader(
+    path: &str,
+    seek_seconds: Option<f64>,
+    output: &OutputParams,
+    conform: ConformStart,
+    use_hwaccel: bool,
+    stream: StreamInfo,
+) -> Result<Box<dyn FrameReader>, DecodeError> {
+    // 開けても最初のフレームで失敗するファイルがあるので、1枚読めるまで確かめる
+    #[cfg(feature = "libav")]
+    match crate::ffmpeg::libav::LibavReader::open(path, seek_seconds, output, conform, stream)
+        .and_then(|mut reader| Ok((reader.read_frame()?, reader)))
+    {
+        Ok((first, reader)) => {
+            return Ok(Box::new(PrimedReader {
+                first,
+                reader: Box::new(reader),
+            }));
+        }
+        Err(e) => warn!("libav decode unavailable, falling back to ffmpeg CLI: {e}"),
+    }
+
+    Ok(Box::new(spawn_rgba_reader(
+        path,
+        seek_seconds,
+        output,
+        conform,
+        use_hwaccel && !stream.alpha.has_alpha,
+        stream,
+    )?))
+}
+
+/// A reader whose first frame was read up front.
+#[cfg(feature = "libav")]
+#[derive(Debug)]
+struct PrimedReader {
+    first: Option<Vec<u8>>,
+    reader: Box<dyn FrameReader>,
+}
+
+#[cfg(feature = "libav")]
+impl FrameReader for PrimedReader {
+    fn read_frame(&mut self) -> Result<Option<Vec<u8>>, DecodeError> {
+        match self.first.take() {
+            Some(frame) => Ok(Some(frame)),
+            None => self.reader.read_frame(),
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::sync::Arc;
+
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::error::DecodeError;
+use crate::ffmpeg::StreamInfo;
+use crate::ffmpeg::command::{SequenceInput, spawn_sequence_reader};
+use crate::ffmpeg::filter::{ConformStart, FrameRate, OutputParams};
+use crate::ffmpeg::index::{MediaIndex, media_index};
+use crate::ffmpeg::reader::{FrameReader, open_frame_reader};
+
+/// How far ahead of its position a session keeps reading instead of seeking again, even if
+/// there is a keyframe in between. Starting ffmpeg costs about as much as decoding this many.
+pub const MAX_FORWARD_SKIP: usize = 240;
+
+/// A long-lived ffmpeg process for one clip. It is started at a seek point and then keeps
+/// streaming forward, so sequential windows never re-decode what came before them.
+#[derive(Debug)]
+pub struct DecodeSession {
+    path: String,
+    /// Position among the file's video streams.
+    video_stream: u32,
+    output: OutputParams,
+    index: Option<Arc<MediaIndex>>,
+    reader: Box<dyn FrameReader>,
+    /// Index of the frame the next read returns.
+    next_frame: usize,
+    /// Output frames per source frame (2 when deinterlacing to field rate, more when
+    /// motion interpolating).
+    subframes: usize,
+    /// Set when frames are project frames at this rate (`OutputParams::conform_fps`).
+    conform_fps: Option<f64>,
+    use_hwaccel: bool,
+    /// Whether any frame has been read since the process was started.
+    produced: bool,
+    ended: bool,
+}
+
+impl DecodeSession {
+    /// Start a session on the `video_stream`-th video stream whose first frame is `start_frame`.
+    /// Frames are counted in output frames, so with field-rate deinterlacing source frame `n`
+    /// is frames `2n` and `2n + 1`, and likewise for interpolated frames. Resampled to a
+    /// project frame rate, they are project frames.
+    pub fn open(
+        path: &str,
+        video_stream: u32,
+        start_frame: usize,
+        output: &OutputParams,
+        use_hwaccel: bool,
+    ) -> Result<Self, DecodeError> {
+        let index = media_index(path, video_stream).ok();
+        let subframes = output.frames_per_source_frame() as usize;
+        let conform_fps = output.conform_fps.map(|FrameRate(fps)| fps);
+        let source_frame = source_frame(index.as_deref(), subframes, conform_fps, start_frame);
+        // 終端より後ろはシーク位置が無いが、先頭から読み直しても何も出てこない
+        if let Some(index) = &index
+            && source_frame >= index.frame_count()
+        {
+            return Err(DecodeError::FrameOutOfRange {
+                path: path.to_string(),
+                frame: start_frame as u32,
+                frame_count: (index.frame_count() * subframes) as u32,
+            });
+        }
+        let seek_seconds = index
+            .as_ref()
+            .and_then(|index| index.seek_time(source_frame as u32));
+        // インデックスが無ければ先頭からデコードして読み飛ばす
+        let next_frame = match seek_seconds {
+            None => 0,
+            Some(_) if conform_fps.is_some() => start_frame,
+            Some(_) => source_frame * subframes,
+        };
+        let conform = ConformStart {
+            first_frame_time: index
+                .as_ref()
+                .filter(|_| seek_seconds.is_some())
+                .and_then(|index| index.time_of(source_frame as u32))
+                .unwrap_or(0.0),
+            frame: next_frame as u32,
+        };
+
+        if let Some(keyframe) = index
+            .as_ref()
+            .and_then(|index| index.keyframe_before(source_frame as u32))
+        {
+            debug!(
+                path,
+                start_frame,
+                keyframe = keyframe.frame,
+                "starting decode session"
+            );
+        }
+
+        let stream = index
+            .as_ref()
+            .map(|index| index.stream())
+            .unwrap_or(StreamInfo {
+                index: video_stream,
+                ..Default::default()
+            });
+        let reader = open_frame_reader(path, seek_seconds, output, conform, use_hwaccel, stream)?;
+        Ok(Self {
+            path: path.to_string(),
+            video_stream,
+            output: output.clone(),
+            index,
+            reader,
+            next_frame,
+            subframes,
+            conform_fps,
+            use_hwaccel,
+            produced: false,
+            ended: false,
+        })
+    }
+
+    /// Start a session on the numbered images matching `pattern`, reading file
+    /// `input.start_number` as frame `start_frame` and the following numbers as the following
+    /// frames. The session ends before the first missing number.
+    pub(crate) fn open_sequence(
+        pattern: &str,
+        input: SequenceInput,
+        start_frame: usize,
+        output: &OutputParams,
+        stream: StreamInfo,
+    ) -> Result<Self, DecodeError> {
+        let reader = spawn_sequence_reader(pattern, input, output, stream)?;
+        Ok(Self {
+            path: pattern.to_string(),
+            video_stream: stream.index,
+            output: output.clone(),
+            index: None,
+            reader: Box::new(reader),
+            next_frame: start_frame,
+            subframes: output.frames_per_source_frame() as usize,
+            conform_fps: None,
+            use_hwaccel: false,
+            produced: false,
+            ended: false,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The frame the next read returns.
+    pub fn next_frame(&self) -> usize {
+        self.next_frame
+    }
+
+    /// Whether `frame` is best reached by reading forward instead of starting a new session:
+    /// it is close, or there is no keyframe between here and it that a seek could start from.
+    pub fn can_reach(&self, frame: usize) -> bool {
+        if self.ended || frame < self.next_frame {
+            return false;
+        }
+        if frame - self.next_frame <= MAX_FORWARD_SKIP {
+            return true;
+        }
+        let source_frame = |frame| {
+            source_frame(
+                self.index.as_deref(),
+                self.subframes,
+                self.conform_fps,
+                frame,
+            )
+        };
+        self.index
+            .as_ref()
+            .and_then(|index| index.keyframe_before(source_frame(frame) as u32))
+            .is_some_and(|keyframe| keyframe.frame as usize <= source_frame(self.next_frame))
+    }
+
+    /// Read frames `start..=end`, skipping forward to `start` first. Stops early at the end
+    /// of the stream, and with `DecodeError::Cancelled` once `cancel` is cancelled.
+    pub fn read_range(
+        &mut self,
+        start: usize,
+        end: usize,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<(usize, Vec<u8>)>, DecodeError> {
+        let mut frames = Vec::new();
+        while self.next_frame <= end {
+            if cancel.is_cancelled() {
+                return Err(DecodeError::Cancelled);
+            }
+            let Some(frame) = self.read_frame()? else {
+                break;
+            };
+            if self.next_frame >= start {
+                frames.push((self.next_frame, frame));
+            }
+            self.next_frame += 1;
+        }
+        Ok(frames)
+    }
+
+    fn read_frame(&mut self) -> Result<Option<Vec<u8>>, DecodeError> {
+        if self.ended {
+            return Ok(None);
+        }
+
+        match self.reader.read_frame() {
+            Ok(Some(frame)) => {
+                self.produced = true;
+                Ok(Some(frame))
+            }
+            Ok(None) => {
+                self.ended = true;
+                Ok(None)
+            }
+            Err(hw_err) if self.use_hwaccel && !self.produced => {
+                // hwaccel が使えない環境ではソフトウェアデコードでやり直す
+                warn!("hwaccel decode failed, falling back to software: {hw_err}");
+                *self = Self::open(
+                    &self.path,
+                    self.video_stream,
+                    self.next_frame,
+                    &self.output,
+                    false,
+                )?;
+                self.read_frame()
+            }
+            Err(e) => {
+                self.ended = true;
+                Err(e)
+            }
+        }
+    }
+}
+
+/// The source frame a session has to start at to output `frame` first. A project frame starts
+/// at the source frame on screen at its time, since the closest frame is that one or a later one.
+fn source_frame(
+    index: Option<&MediaIndex>,
+    subframes: usize,
+    conform_fps: Option<f64>,
+    frame: usize,
+) -> usize {
+    match conform_fps {
+        Some(fps) => index
+            .and_then(|index| index.frame_at(frame as f64 / fps))
+            .unwrap_or(0) as usize,
+        None => frame / subframes,
+    }
+}
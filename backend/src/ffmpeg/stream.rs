@@ -0,0 +1,219 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ffmpeg::{FfprobeStream, parse_duration_seconds, run_ffprobe};
+
+/// What a stream carries, from ffprobe's `codec_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Video,
+    Audio,
+    Subtitle,
+    Data,
+    Attachment,
+}
+
+impl MediaKind {
+    fn of(codec_type: &str) -> Option<Self> {
+        match codec_type {
+            "video" => Some(Self::Video),
+            "audio" => Some(Self::Audio),
+            "subtitle" => Some(Self::Subtitle),
+            "data" => Some(Self::Data),
+            "attachment" => Some(Self::Attachment),
+            _ => None,
+        }
+    }
+
+    /// The stream specifier letter ffmpeg and ffprobe use for this kind.
+    fn letter(self) -> &'static str {
+        match self {
+            Self::Video => "v",
+            Self::Audio => "a",
+            Self::Subtitle => "s",
+            Self::Data => "d",
+            Self::Attachment => "t",
+        }
+    }
+}
+
+impl fmt::Display for MediaKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Video => "video",
+            Self::Audio => "audio",
+            Self::Subtitle => "subtitle",
+            Self::Data => "data",
+            Self::Attachment => "attachment",
+        })
+    }
+}
+
+/// The specifier of the `position`-th stream of `kind`, e.g. `a:1` for the second audio stream.
+pub fn stream_specifier(kind: MediaKind, position: u32) -> String {
+    format!("{}:{position}", kind.letter())
+}
+
+/// Picks one stream of a kind in a multi-track file. Without any field the first one is used.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct StreamSelector {
+    /// Position among the streams of the kind (`kind_index` in `GET /video/meta`). With
+    /// `language` or `title`, position among the streams that match them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    /// Language tag, e.g. `"jpn"`. Compared case-insensitively.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Part of the stream title, e.g. `"mic"`. Compared case-insensitively.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+}
+
+impl StreamSelector {
+    /// Position of the selected stream among the streams of `kind`, or `None` if no stream
+    /// matches. The file is only probed when matching by language or title.
+    pub fn resolve(&self, path: &str, kind: MediaKind) -> Result<Option<u32>, String> {
+        let nth = self.index.unwrap_or(0) as usize;
+        if self.language.is_none() && self.title.is_none() {
+            return Ok(Some(nth as u32));
+        }
+        Ok(probe_streams(path)?
+            .into_iter()
+            .filter(|stream| stream.kind == Some(kind) && self.matches(stream))
+            .nth(nth)
+            .map(|stream| stream.kind_index))
+    }
+
+    fn matches(&self, stream: &StreamEntry) -> bool {
+        let language = self.language.as_deref().is_none_or(|language| {
+            stream
+                .language
+                .as_deref()
+                .is_some_and(|tag| tag.eq_ignore_ascii_case(language.trim()))
+        });
+        let title = self.title.as_deref().is_none_or(|title| {
+            stream
+                .title
+                .as_deref()
+                .is_some_and(|tag| tag.to_lowercase().contains(&title.trim().to_lowercase()))
+        });
+        language && title
+    }
+}
+
+impl fmt::Display for StreamSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(index) = self.index {
+            parts.push(format!("index={index}"));
+        }
+        if let Some(language) = &self.language {
+            parts.push(format!("language={language}"));
+        }
+        if let Some(title) = &self.title {
+            parts.push(format!("title={title}"));
+        }
+        if parts.is_empty() {
+            f.write_str("first stream")
+        } else {
+            f.write_str(&parts.join(", "))
+        }
+    }
+}
+
+/// One stream of a file, as listed by `GET /video/meta` and `GET /audio/meta`.
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamEntry {
+    /// Index in the file, counting every stream.
+    pub index: u32,
+    /// `None` for stream types ffmpeg does not name.
+    pub kind: Option<MediaKind>,
+    /// Position among the streams of the same kind; what `StreamSelector::index` refers to.
+    pub kind_index: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Marked as the default track of its kind.
+    pub default: bool,
+    /// Cover art stored as a video stream.
+    pub attached_pic: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel_layout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+}
+
+/// List every stream of `path`, in file order.
+pub fn probe_streams(path: &str) -> Result<Vec<StreamEntry>, String> {
+    let output = run_ffprobe(
+        path,
+        None,
+        "stream=index,codec_type,codec_name,width,height,channels,channel_layout,sample_rate,duration:stream_tags=language,title:stream_disposition=default,attached_pic",
+    )?;
+
+    let mut counts = [0u32; 5];
+    let mut entries = Vec::new();
+    for stream in output.streams.unwrap_or_default() {
+        let kind = stream.codec_type.as_deref().and_then(MediaKind::of);
+        let kind_index = match kind {
+            Some(kind) => {
+                let count = &mut counts[kind as usize];
+                *count += 1;
+                *count - 1
+            }
+            None => 0,
+        };
+        entries.push(StreamEntry {
+            index: stream.index.unwrap_or(entries.len() as u32),
+            kind,
+            kind_index,
+            codec_name: stream.codec_name.clone(),
+            language: tag(&stream, "language"),
+            title: tag(&stream, "title"),
+            default: disposition(&stream, "default"),
+            attached_pic: disposition(&stream, "attached_pic"),
+            duration_ms: parse_duration_seconds(stream.duration.as_deref())
+                .map(|seconds| (seconds * 1000.0).round() as u64),
+            width: stream.width.filter(|_| kind == Some(MediaKind::Video)),
+            height: stream.height.filter(|_| kind == Some(MediaKind::Video)),
+            channels: stream.channels,
+            channel_layout: stream.channel_layout.clone(),
+            sample_rate: stream
+                .sample_rate
+                .as_deref()
+                .and_then(|rate| rate.trim().parse().ok()),
+        });
+    }
+    Ok(entries)
+}
+
+fn tag(stream: &FfprobeStream, name: &str) -> Option<String> {
+    stream
+        .tags
+        .as_ref()?
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.trim().to_string())
+        .filter(|value| !value.is_empty() && value != "und")
+}
+
+fn disposition(stream: &FfprobeStream, name: &str) -> bool {
+    stream
+        .disposition
+        .as_ref()
+        .and_then(|disposition| disposition.get(name))
+        .is_some_and(|value| *value != 0)
+}
@@ -5,9 +5,11 @@ use std::{
 
 use manual_future::{ManualFuture, ManualFutureCompleter};
 
+type SharedState<T> = (Option<Arc<T>>, Vec<ManualFutureCompleter<Arc<T>>>);
+
 #[derive(Debug)]
 pub struct SharedManualFuture<T: Send> {
-    value: Arc<Mutex<(Option<Arc<T>>, Vec<ManualFutureCompleter<Arc<T>>>)>>,
+    value: Arc<Mutex<SharedState<T>>>,
 }
 
 impl<T: Send> SharedManualFuture<T> {
@@ -66,6 +68,12 @@ impl<T: Send> SharedManualFuture<T> {
     }
 }
 
+impl<T: Send> Default for SharedManualFuture<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T: Send> Clone for SharedManualFuture<T> {
     fn clone(&self) -> Self {
         Self {
@@ -1,9 +1,17 @@
+pub mod cache;
 pub mod decoder;
+pub mod disk_cache;
+pub mod error;
 pub mod ffmpeg;
 pub mod future;
+pub mod protocol;
+pub mod remap;
+pub mod resize;
+pub mod scheduler;
+pub mod source;
 pub mod util;
 
-use std::{net::SocketAddr, ops::Bound, sync::atomic::AtomicBool};
+use std::{net::SocketAddr, ops::Bound, path::Path, sync::atomic::AtomicBool};
 
 use axum::{
     Router,
@@ -24,22 +32,72 @@ use std::sync::atomic::{AtomicUsize, Ordering};
 use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
 use tokio::net::TcpListener;
 use tokio_util::io::ReaderStream;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::{
-    decoder::{DECODER, DecoderKey, set_max_cache_size},
-    ffmpeg::{probe_audio_duration_ms, probe_video_duration_ms, probe_video_fps},
+    cache::set_max_cache_size,
+    decoder::{DECODER, DecodedFrame, DecoderKey},
+    disk_cache::{DEFAULT_MAX_GIB, configure_disk_cache},
+    error::DecodeError,
+    ffmpeg::{
+        StreamInfo,
+        color::{ColorConversion, ToneMapping},
+        deinterlace::FieldOrder,
+        filter::OutputParams,
+        index::{clear_media_indexes, media_index},
+        probe_audio_duration_ms, probe_stream_info, probe_video_duration_ms, probe_video_fps,
+        stream::{MediaKind, StreamEntry, StreamSelector, probe_streams},
+    },
+    protocol::{FrameFlags, FrameHeader, MediaTime, PixelFormat, ServerMessage, TIMESTAMP_UNKNOWN},
+    remap::{ConformMode, FrameBlend, RemapPiece, TimeRemap},
+    scheduler::{DecodePriority, SCHEDULER},
+    source::{
+        GapMode, ImageSequence, SequenceOptions, SourceKind, is_image_path, is_sequence_path,
+    },
     util::resolve_path_to_string,
 };
 
 #[derive(Deserialize)]
 struct VideoQuery {
     path: String,
+    /// Image sequences only; see `SequenceOptions`.
+    fps: Option<f64>,
+    start_number: Option<u32>,
+    #[serde(default)]
+    gaps: GapMode,
+    /// Video stream to describe; see `StreamSelector`.
+    stream: Option<u32>,
+    language: Option<String>,
+    title: Option<String>,
+}
+
+impl VideoQuery {
+    fn selector(&self) -> StreamSelector {
+        StreamSelector {
+            index: self.stream,
+            language: self.language.clone(),
+            title: self.title.clone(),
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct AudioQuery {
     path: String,
+    /// Audio stream to describe; see `StreamSelector`.
+    stream: Option<u32>,
+    language: Option<String>,
+    title: Option<String>,
+}
+
+impl AudioQuery {
+    fn selector(&self) -> StreamSelector {
+        StreamSelector {
+            index: self.stream,
+            language: self.language.clone(),
+            title: self.title.clone(),
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -48,9 +106,78 @@ struct AppState;
 #[derive(Deserialize, Debug)]
 struct FrameRequest {
     video: String,
-    width: u32,
-    height: u32,
+    /// `width`, `height` and optional `scale` / `crop`.
+    #[serde(flatten)]
+    output: OutputParams,
+    /// Source frame index, or with `project_fps` a project frame counted from the start of the
+    /// source. When `time` is set this is only echoed back in the packet header.
+    #[serde(default)]
     frame: u32,
+    /// Presentation time in seconds from the first frame. Takes precedence over `frame`.
+    #[serde(default)]
+    time: Option<MediaTime>,
+    /// Speed change, reverse, freeze or speed curve. With it, `time` is the time into the clip
+    /// on the timeline and is mapped to a source time first.
+    #[serde(default)]
+    remap: Option<TimeRemap>,
+    /// Frame rate of the project. With it, `frame` is mapped to source frames by `conform`.
+    #[serde(default)]
+    project_fps: Option<f64>,
+    /// `"nearest"` (default), `"blend"` or `"interpolate"`.
+    #[serde(default)]
+    conform: ConformMode,
+    /// `"interactive"` (default) or `"render"`. Decides who gets a free ffmpeg slot first.
+    #[serde(default)]
+    priority: DecodePriority,
+    /// `"auto"` (default), `"ffmpeg"`, `"ffmpeg_software"`, `"still"` or `"sequence"`.
+    #[serde(default)]
+    source: SourceKind,
+    /// Frame rate, start number and gap handling of an image sequence.
+    #[serde(default)]
+    sequence: SequenceOptions,
+    /// Video stream of a multi-track file, by `index`, `language` or `title`. Defaults to the first.
+    #[serde(default)]
+    stream: StreamSelector,
+}
+
+/// Hint that frames `start..=end` of `video` with the given output will be requested soon.
+#[derive(Deserialize, Debug)]
+struct PrefetchRequest {
+    video: String,
+    #[serde(flatten)]
+    output: OutputParams,
+    start: u32,
+    end: u32,
+    /// When set, `start..=end` are project frames at this rate.
+    #[serde(default)]
+    project_fps: Option<f64>,
+    /// How project frames map to source frames, as in frame requests.
+    #[serde(default)]
+    conform: ConformMode,
+    /// Higher runs first. Defaults to 0.
+    #[serde(default)]
+    priority: u8,
+    #[serde(default)]
+    source: SourceKind,
+    #[serde(default)]
+    sequence: SequenceOptions,
+    #[serde(default)]
+    stream: StreamSelector,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum TaggedClientMessage {
+    Frame(FrameRequest),
+    Prefetch(PrefetchRequest),
+}
+
+/// Text message received on `/ws`. Untyped messages are frame requests.
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum ClientMessage {
+    Tagged(TaggedClientMessage),
+    Frame(FrameRequest),
 }
 
 #[derive(Deserialize)]
@@ -58,6 +185,21 @@ struct CacheSizeRequest {
     gib: usize,
 }
 
+#[derive(Deserialize)]
+struct DecodeConcurrencyRequest {
+    max_processes: usize,
+}
+
+#[derive(Deserialize)]
+struct DiskCacheRequest {
+    enabled: bool,
+    /// Defaults to `<project>/.framescript/cache` or the user cache dir.
+    #[serde(default)]
+    dir: Option<String>,
+    #[serde(default)]
+    gib: Option<u64>,
+}
+
 #[derive(Deserialize)]
 struct ProgressRequest {
     completed: Option<usize>,
@@ -73,8 +215,17 @@ struct ProgressResponse {
 #[derive(Deserialize, Clone)]
 #[serde(tag = "kind", rename_all = "lowercase")]
 enum AudioSourceRef {
-    Video { path: String },
-    Sound { path: String },
+    Video {
+        path: String,
+        /// Audio stream of a multi-track file. Defaults to the first.
+        #[serde(default)]
+        stream: StreamSelector,
+    },
+    Sound {
+        path: String,
+        #[serde(default)]
+        stream: StreamSelector,
+    },
 }
 
 #[derive(Deserialize, Clone)]
@@ -87,6 +238,9 @@ struct AudioSegment {
     source_start_frame: i64,
     #[serde(rename = "durationFrames")]
     duration_frames: i64,
+    /// The remap of the clip the audio belongs to. Replaces `sourceStartFrame`.
+    #[serde(default)]
+    remap: Option<TimeRemap>,
 }
 
 #[derive(Deserialize, Clone)]
@@ -98,8 +252,15 @@ struct AudioPlanRequest {
 #[derive(Serialize, Clone)]
 #[serde(tag = "kind", rename_all = "lowercase")]
 enum AudioSourceResolved {
-    Video { path: String },
-    Sound { path: String },
+    /// `stream` is the position among the file's audio streams (`a:N`).
+    Video {
+        path: String,
+        stream: u32,
+    },
+    Sound {
+        path: String,
+        stream: u32,
+    },
 }
 
 #[derive(Serialize, Clone)]
@@ -110,8 +271,15 @@ struct AudioSegmentResolved {
     project_start_frame: i64,
     #[serde(rename = "sourceStartFrame")]
     source_start_frame: i64,
+    /// Exact source start of a remapped piece, which rarely falls on a frame. Takes over from
+    /// `sourceStartFrame` when set.
+    #[serde(rename = "sourceStartSeconds", skip_serializing_if = "Option::is_none")]
+    source_start_seconds: Option<f64>,
     #[serde(rename = "durationFrames")]
     duration_frames: i64,
+    /// Playback speed of remapped audio. Negative plays the stretch from `sourceStartFrame`
+    /// backwards.
+    speed: f64,
 }
 
 #[derive(Serialize, Clone)]
@@ -148,10 +316,19 @@ async fn main() {
             "/audio/meta",
             get(audio_meta_handler).options(options_handler),
         )
+        .route("/prefetch", post(prefetch_handler).options(options_handler))
         .route(
             "/set_cache_size",
             post(set_cache_size_handler).options(options_handler),
         )
+        .route(
+            "/set_decode_concurrency",
+            post(set_decode_concurrency_handler).options(options_handler),
+        )
+        .route(
+            "/set_disk_cache",
+            post(set_disk_cache_handler).options(options_handler),
+        )
         .route(
             "/render_progress",
             post(set_progress_handler)
@@ -169,6 +346,10 @@ async fn main() {
                 .options(options_handler),
         )
         .route("/reset", post(reset_handler).options(options_handler))
+        .route(
+            "/decoder_stats",
+            get(decoder_stats_handler).options(options_handler),
+        )
         .route(
             "/is_canceled",
             get(is_canceled_handler).options(options_handler),
@@ -190,7 +371,7 @@ async fn ws_handler(ws: WebSocketUpgrade, State(state): State<AppState>) -> impl
 
 async fn video_handler(
     State(_state): State<AppState>,
-    Query(VideoQuery { path }): Query<VideoQuery>,
+    Query(VideoQuery { path, .. }): Query<VideoQuery>,
     range: Option<TypedHeader<Range>>,
 ) -> Result<impl IntoResponse, StatusCode> {
     let resolved_path = resolve_path_to_string(&path).map_err(|_| StatusCode::BAD_REQUEST)?;
@@ -276,7 +457,7 @@ async fn video_handler(
 
 async fn audio_handler(
     State(_state): State<AppState>,
-    Query(AudioQuery { path }): Query<AudioQuery>,
+    Query(AudioQuery { path, .. }): Query<AudioQuery>,
     range: Option<TypedHeader<Range>>,
 ) -> Result<impl IntoResponse, StatusCode> {
     let resolved_path = resolve_path_to_string(&path).map_err(|_| StatusCode::BAD_REQUEST)?;
@@ -366,23 +547,109 @@ async fn healthz_handler() -> impl IntoResponse {
     (headers, StatusCode::OK)
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Default)]
 struct VideoMetadataResponse {
     duration_ms: u64,
     fps: f64,
+    /// Reported for image sequences and images, whose length is counted in frames.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frame_count: Option<u32>,
+    /// How long each frame of an image is shown, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frame_delays_ms: Option<Vec<f64>>,
+    has_alpha: bool,
+    /// Size as displayed, after `rotation` is applied.
+    width: u32,
+    height: u32,
+    /// Clockwise degrees the coded picture is turned for display (0, 90, 180, 270).
+    rotation: u32,
+    /// Conversion to RGB applied when decoding (with the default tone mapping).
+    color: ColorConversion,
+    /// Interlacing, for choosing a `deinterlace` mode.
+    field_order: FieldOrder,
+    /// Every stream of the file, for picking one with `stream` / `language` / `title`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    streams: Vec<StreamEntry>,
+}
+
+impl VideoMetadataResponse {
+    fn with_stream(mut self, stream: StreamInfo) -> Self {
+        (self.width, self.height) = stream.display_size();
+        self.rotation = stream.orientation.rotation;
+        self.has_alpha = stream.alpha.has_alpha;
+        self.field_order = stream.field_order;
+        self.color = stream
+            .color
+            .conversion(stream.height, ToneMapping::default());
+        self
+    }
 }
 
 async fn video_meta_handler(
     State(_state): State<AppState>,
-    Query(VideoQuery { path }): Query<VideoQuery>,
+    Query(query): Query<VideoQuery>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let resolved_path = resolve_path_to_string(&path).map_err(|_| StatusCode::BAD_REQUEST)?;
-    let duration_ms =
-        probe_video_duration_ms(&resolved_path).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let resolved_path = resolve_path_to_string(&query.path).map_err(|_| StatusCode::BAD_REQUEST)?;
 
-    let fps = probe_video_fps(&resolved_path).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let metadata = if is_sequence_path(&resolved_path) {
+        let options = SequenceOptions {
+            fps: query.fps,
+            start_number: query.start_number,
+            gaps: query.gaps,
+        };
+        let sequence =
+            ImageSequence::scan(&resolved_path, &options).map_err(|_| StatusCode::BAD_REQUEST)?;
+        let frame_count = sequence.frame_count();
+        VideoMetadataResponse {
+            duration_ms: (frame_count as f64 / sequence.fps() * 1000.0).round() as u64,
+            fps: sequence.fps(),
+            frame_count: Some(frame_count),
+            frame_delays_ms: None,
+            ..Default::default()
+        }
+        .with_stream(sequence.stream())
+    } else if is_image_path(&resolved_path) {
+        let index = media_index(&resolved_path, 0).map_err(|_| StatusCode::BAD_REQUEST)?;
+        let frame_delays_ms = index
+            .frame_delays()
+            .into_iter()
+            .map(|delay| delay * 1000.0)
+            .collect::<Vec<_>>();
+        let total_ms: f64 = frame_delays_ms.iter().sum();
+        VideoMetadataResponse {
+            duration_ms: total_ms.round() as u64,
+            // 可変の表示時間を平均したもの。静止画は 0
+            fps: if total_ms > 0.0 {
+                index.frame_count() as f64 * 1000.0 / total_ms
+            } else {
+                0.0
+            },
+            frame_count: Some(index.frame_count() as u32),
+            frame_delays_ms: Some(frame_delays_ms),
+            ..Default::default()
+        }
+        .with_stream(index.stream())
+    } else {
+        let stream = query
+            .selector()
+            .resolve(&resolved_path, MediaKind::Video)
+            .map_err(|_| StatusCode::BAD_REQUEST)?
+            .ok_or(StatusCode::NOT_FOUND)?;
+        let duration_ms =
+            probe_video_duration_ms(&resolved_path, stream).map_err(|_| StatusCode::BAD_REQUEST)?;
+        let fps = probe_video_fps(&resolved_path, stream).map_err(|_| StatusCode::BAD_REQUEST)?;
+        VideoMetadataResponse {
+            duration_ms,
+            fps,
+            frame_count: None,
+            frame_delays_ms: None,
+            streams: probe_streams(&resolved_path).unwrap_or_default(),
+            ..Default::default()
+        }
+        .with_stream(probe_stream_info(&resolved_path, stream).unwrap_or_default())
+    };
 
-    let mut resp = Json(VideoMetadataResponse { duration_ms, fps }).into_response();
+    let mut resp = Json(metadata).into_response();
     apply_cors(resp.headers_mut());
     Ok(resp)
 }
@@ -390,17 +657,31 @@ async fn video_meta_handler(
 #[derive(Serialize)]
 struct AudioMetadataResponse {
     duration_ms: u64,
+    /// Position of the described stream among the file's audio streams.
+    stream: u32,
+    streams: Vec<StreamEntry>,
 }
 
 async fn audio_meta_handler(
     State(_state): State<AppState>,
-    Query(AudioQuery { path }): Query<AudioQuery>,
+    Query(query): Query<AudioQuery>,
 ) -> Result<impl IntoResponse, StatusCode> {
-    let resolved_path = resolve_path_to_string(&path).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let resolved_path = resolve_path_to_string(&query.path).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let stream = query
+        .selector()
+        .resolve(&resolved_path, MediaKind::Audio)
+        .map_err(|_| StatusCode::BAD_REQUEST)?
+        .ok_or(StatusCode::NOT_FOUND)?;
     let duration_ms =
-        probe_audio_duration_ms(&resolved_path).map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    let mut resp = Json(AudioMetadataResponse { duration_ms }).into_response();
+        probe_audio_duration_ms(&resolved_path, stream).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let streams = probe_streams(&resolved_path).unwrap_or_default();
+
+    let mut resp = Json(AudioMetadataResponse {
+        duration_ms,
+        stream,
+        streams,
+    })
+    .into_response();
     apply_cors(resp.headers_mut());
     Ok(resp)
 }
@@ -419,35 +700,64 @@ async fn handle_socket(mut socket: WebSocket, _state: AppState) {
 
         match msg {
             Message::Text(text) => {
-                let req: FrameRequest = match serde_json::from_str(&text) {
-                    Ok(r) => r,
+                let req = match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Frame(r))
+                    | Ok(ClientMessage::Tagged(TaggedClientMessage::Frame(r))) => r,
+                    Ok(ClientMessage::Tagged(TaggedClientMessage::Prefetch(r))) => {
+                        queue_prefetch(r).await;
+                        continue;
+                    }
                     Err(e) => {
                         error!("invalid request: {e}, text={text}");
                         continue;
                     }
                 };
 
-                let width = req.width;
-                let height = req.height;
                 let target_frame = req.frame;
 
-                let path = resolve_path_to_string(&req.video).unwrap_or_default();
-
-                let decoder = DECODER
-                    .cached_decoder(DecoderKey {
-                        path,
-                        width,
-                        height,
-                    })
-                    .await;
-                let frame_rgba = decoder.get_frame(target_frame).await;
-
-                // into [width][height][frame_index][rgba...] packet
-                let mut packet = Vec::with_capacity(12 + frame_rgba.len());
-                packet.extend_from_slice(&width.to_le_bytes());
-                packet.extend_from_slice(&height.to_le_bytes());
-                packet.extend_from_slice(&target_frame.to_le_bytes());
-                packet.extend_from_slice(&frame_rgba);
+                let packet = match decode_requested_frame(&req).await {
+                    Ok(frame) => {
+                        let header = FrameHeader {
+                            width: frame.width,
+                            height: frame.height,
+                            pixel_format: PixelFormat::Rgba8,
+                            flags: frame.flags,
+                            requested_frame: target_frame,
+                            source_frame: frame.source_frame,
+                            timestamp_us: frame.timestamp_us,
+                        };
+                        header.encode(&frame.rgba)
+                    }
+                    Err(e) => {
+                        error!(
+                            "failed to decode frame {target_frame} of {}: {e}",
+                            req.video
+                        );
+
+                        let message = ServerMessage::Error {
+                            video: req.video.clone(),
+                            frame: target_frame,
+                            message: e.to_string(),
+                            error: e,
+                        };
+                        let text = serde_json::to_string(&message).unwrap_or_default();
+                        if let Err(e) = socket.send(Message::Text(text.into())).await {
+                            error!("failed to send error: {e}");
+                            break;
+                        }
+
+                        let header = FrameHeader {
+                            width: req.output.width,
+                            height: req.output.height,
+                            pixel_format: PixelFormat::Rgba8,
+                            flags: FrameFlags::DECODE_ERROR | FrameFlags::PLACEHOLDER,
+                            requested_frame: target_frame,
+                            source_frame: target_frame,
+                            timestamp_us: TIMESTAMP_UNKNOWN,
+                        };
+                        header.encode(&[])
+                    }
+                };
 
                 let bytes = Bytes::from(packet);
 
@@ -471,6 +781,116 @@ async fn handle_socket(mut socket: WebSocket, _state: AppState) {
     info!("client disconnected");
 }
 
+async fn decode_requested_frame(req: &FrameRequest) -> Result<DecodedFrame, DecodeError> {
+    let path = resolve_path_to_string(&req.video).map_err(|e| DecodeError::InvalidPath {
+        path: req.video.clone(),
+        reason: e.to_string(),
+    })?;
+
+    let mut output = resolve_output(&req.output)?;
+    if let Some(remap) = &req.remap {
+        output.interpolate = output.interpolate.max(remap.interpolation_factor());
+    }
+
+    let key = DecoderKey {
+        path,
+        output,
+        source: req.source,
+        sequence: req.sequence,
+        stream: req.stream.clone(),
+    };
+    let decoder = DECODER.cached_decoder(key.clone()).await;
+    decoder.open_source().await?;
+    let project_fps = req.project_fps.filter(|fps| fps.is_finite() && *fps > 0.0);
+    // リマップはクリップ内の時刻で指定するので、プロジェクトのフレームも時刻に直す
+    let seconds = match (req.time.and_then(MediaTime::seconds), project_fps) {
+        (Some(seconds), _) => Some(seconds),
+        (None, Some(fps)) if req.remap.is_some() => Some(req.frame as f64 / fps),
+        (None, _) => None,
+    };
+
+    let frame = match (seconds, &req.remap, project_fps) {
+        (Some(seconds), Some(remap), _) => {
+            let seconds = remap.source_time(seconds);
+            if remap.blend == FrameBlend::Frames {
+                return decoder.get_blended_frame(seconds, req.priority).await;
+            }
+            decoder.frame_at_time(seconds)?
+        }
+        (Some(seconds), None, _) => decoder.frame_at_time(seconds)?,
+        (None, _, Some(fps)) => {
+            // 補間するときは出力フレーム数の違うデコーダに切り替える
+            let decoder = DECODER.conformed_decoder(key, fps, req.conform).await?;
+            return decoder
+                .get_conformed_frame(req.frame, fps, req.conform, req.priority)
+                .await;
+        }
+        (None, _, None) => req.frame,
+    };
+    decoder.get_frame(frame, req.priority).await
+}
+
+/// Resolve the LUT path of `output` like clip paths, so the decoder key and ffmpeg see the
+/// same absolute file.
+fn resolve_output(output: &OutputParams) -> Result<OutputParams, DecodeError> {
+    let mut output = output.clone();
+    if let Some(lut) = output.grade.lut.take() {
+        let resolved = resolve_path_to_string(&lut).map_err(|e| DecodeError::InvalidPath {
+            path: lut.clone(),
+            reason: e.to_string(),
+        })?;
+        if !Path::new(&resolved).is_file() {
+            return Err(DecodeError::FileNotFound { path: lut });
+        }
+        output.grade.lut = Some(resolved);
+    }
+    Ok(output)
+}
+
+async fn queue_prefetch(req: PrefetchRequest) {
+    let path = match resolve_path_to_string(&req.video) {
+        Ok(path) => path,
+        Err(e) => {
+            error!("invalid prefetch path {}: {e}", req.video);
+            return;
+        }
+    };
+    let output = match resolve_output(&req.output) {
+        Ok(output) => output,
+        Err(e) => {
+            error!("invalid prefetch output for {}: {e}", req.video);
+            return;
+        }
+    };
+
+    DECODER
+        .prefetch(
+            DecoderKey {
+                path,
+                output,
+                source: req.source,
+                sequence: req.sequence,
+                stream: req.stream,
+            },
+            req.start,
+            req.end,
+            req.project_fps.filter(|fps| fps.is_finite() && *fps > 0.0),
+            req.conform,
+            req.priority,
+        )
+        .await;
+}
+
+async fn prefetch_handler(
+    State(_state): State<AppState>,
+    Json(payload): Json<PrefetchRequest>,
+) -> impl IntoResponse {
+    let mut headers = HeaderMap::new();
+    apply_cors(&mut headers);
+    queue_prefetch(payload).await;
+    (headers, StatusCode::ACCEPTED)
+}
+
 async fn options_handler() -> impl IntoResponse {
     let mut headers = HeaderMap::new();
     apply_cors(&mut headers);
@@ -484,13 +904,58 @@ async fn set_cache_size_handler(
     let mut headers = HeaderMap::new();
     apply_cors(&mut headers);
 
-    let gib = payload.gib.max(1).min(128); // clamp to a sane range
-    let bytes = gib as usize * 1024 * 1024 * 1024;
+    let gib = payload.gib.clamp(1, 128); // clamp to a sane range
+    let bytes = gib * 1024 * 1024 * 1024;
     set_max_cache_size(bytes);
 
     (headers, StatusCode::OK)
 }
 
+async fn set_decode_concurrency_handler(
+    State(_state): State<AppState>,
+    Json(payload): Json<DecodeConcurrencyRequest>,
+) -> impl IntoResponse {
+    let mut headers = HeaderMap::new();
+    apply_cors(&mut headers);
+    SCHEDULER.set_max_running(payload.max_processes.clamp(1, 64));
+    (headers, StatusCode::OK)
+}
+
+async fn set_disk_cache_handler(
+    State(_state): State<AppState>,
+    Json(payload): Json<DiskCacheRequest>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let mut headers = HeaderMap::new();
+    apply_cors(&mut headers);
+
+    let config = match payload.enabled {
+        true => {
+            let dir = match payload.dir {
+                Some(dir) => Some(
+                    resolve_path_to_string(&dir)
+                        .map_err(|_| StatusCode::BAD_REQUEST)?
+                        .into(),
+                ),
+                None => None,
+            };
+            let gib = payload.gib.unwrap_or(DEFAULT_MAX_GIB).clamp(1, 4096);
+            Some((dir, gib * 1024 * 1024 * 1024))
+        }
+        false => None,
+    };
+
+    // 既存のキャッシュファイルを走査するのでブロッキングスレッドで行う
+    tokio::task::spawn_blocking(move || configure_disk_cache(config))
+        .await
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
+        .map_err(|e| {
+            error!("failed to configure disk cache: {e}");
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+
+    Ok((headers, StatusCode::OK))
+}
+
 async fn set_progress_handler(
     State(_state): State<AppState>,
     Json(payload): Json<ProgressRequest>,
@@ -541,11 +1006,18 @@ async fn reset_handler(State(_state): State<AppState>) -> impl IntoResponse {
     let mut headers = HeaderMap::new();
     apply_cors(&mut headers);
     DECODER.clear().await;
+    clear_media_indexes();
     RENDER_CANCEL.store(false, Ordering::Relaxed);
     *RENDER_AUDIO_PLAN.lock().unwrap() = None;
     (headers, StatusCode::OK)
 }
 
+async fn decoder_stats_handler(State(_state): State<AppState>) -> impl IntoResponse {
+    let mut headers = HeaderMap::new();
+    apply_cors(&mut headers);
+    (headers, Json(DECODER.stats()))
+}
+
 async fn set_audio_plan_handler(
     State(_state): State<AppState>,
     Json(payload): Json<AudioPlanRequest>,
@@ -569,43 +1041,94 @@ async fn set_audio_plan_handler(
         let project_start_frame = seg.project_start_frame.max(0);
         let source_start_frame = seg.source_start_frame.max(0);
 
-        let resolved_source = match seg.source {
-            AudioSourceRef::Video { path } => resolve_path_to_string(&path)
-                .ok()
-                .map(|p| AudioSourceResolved::Video { path: p }),
-            AudioSourceRef::Sound { path } => resolve_path_to_string(&path)
-                .ok()
-                .map(|p| AudioSourceResolved::Sound { path: p }),
+        let (path, selector) = match &seg.source {
+            AudioSourceRef::Video { path, stream } | AudioSourceRef::Sound { path, stream } => {
+                (path, stream)
+            }
         };
-
-        let Some(source) = resolved_source else {
+        let Ok(source_path) = resolve_path_to_string(path) else {
             continue;
         };
+        let stream = match selector.resolve(&source_path, MediaKind::Audio) {
+            Ok(Some(stream)) => stream,
+            Ok(None) => {
+                warn!("no audio stream of {source_path} matches {selector}");
+                continue;
+            }
+            Err(e) => {
+                warn!("failed to list streams of {source_path}: {e}");
+                continue;
+            }
+        };
 
         // Validate that the source actually has an audio stream, and clamp the segment to its duration.
-        let source_path = match &source {
-            AudioSourceResolved::Video { path } => path.as_str(),
-            AudioSourceResolved::Sound { path } => path.as_str(),
-        };
-        let source_duration_ms = match probe_audio_duration_ms(source_path) {
+        let source_duration_ms = match probe_audio_duration_ms(&source_path, stream) {
             Ok(ms) if ms > 0 => ms,
             _ => continue,
         };
-        let source_total_frames =
-            ((source_duration_ms as f64 / 1000.0) * fps).round().max(0.0) as i64;
-        let available = (source_total_frames - source_start_frame).max(0);
-        let duration_frames = duration_frames.min(available);
-        if duration_frames == 0 {
+        let source_total_frames = ((source_duration_ms as f64 / 1000.0) * fps)
+            .round()
+            .max(0.0) as i64;
+
+        let source = match seg.source {
+            AudioSourceRef::Video { .. } => AudioSourceResolved::Video {
+                path: source_path,
+                stream,
+            },
+            AudioSourceRef::Sound { .. } => AudioSourceResolved::Sound {
+                path: source_path,
+                stream,
+            },
+        };
+
+        let Some(remap) = &seg.remap else {
+            let available = (source_total_frames - source_start_frame).max(0);
+            let duration_frames = duration_frames.min(available);
+            if duration_frames == 0 {
+                continue;
+            }
+            segments.push(AudioSegmentResolved {
+                id: seg.id,
+                source,
+                project_start_frame,
+                source_start_frame,
+                source_start_seconds: None,
+                duration_frames,
+                speed: 1.0,
+            });
             continue;
-        }
+        };
 
-        segments.push(AudioSegmentResolved {
-            id: seg.id,
-            source,
-            project_start_frame,
-            source_start_frame,
-            duration_frames,
-        });
+        // 速度が一定の区間ごとに分ける。フリーズは無音にし、音源の外にはみ出す分は削る
+        let source_seconds = source_duration_ms as f64 / 1000.0;
+        let pieces = remap.pieces(fps, duration_frames);
+        for (n, piece) in pieces.into_iter().enumerate() {
+            let RemapPiece {
+                start_frame,
+                frames,
+                source_start,
+                speed,
+            } = piece;
+            let available = source_seconds - source_start;
+            if speed.abs() < 1e-6 || available <= 0.0 {
+                continue;
+            }
+            let covered = ((available / speed.abs() * fps).floor() as i64).min(frames);
+            if covered <= 0 {
+                continue;
+            }
+            // 逆再生では音源の外になるのは区間の頭の方
+            let skipped = if speed < 0.0 { frames - covered } else { 0 };
+            segments.push(AudioSegmentResolved {
+                id: format!("{}#{n}", seg.id),
+                source: source.clone(),
+                project_start_frame: project_start_frame + start_frame + skipped,
+                source_start_frame: (source_start * fps).round() as i64,
+                source_start_seconds: Some(source_start),
+                duration_frames: covered,
+                speed,
+            });
+        }
     }
 
     *RENDER_AUDIO_PLAN.lock().unwrap() = Some(AudioPlanResolved { fps, segments });
@@ -617,10 +1140,14 @@ async fn get_audio_plan_handler(State(_state): State<AppState>) -> impl IntoResp
     let mut headers = HeaderMap::new();
     apply_cors(&mut headers);
 
-    let plan = RENDER_AUDIO_PLAN.lock().unwrap().clone().unwrap_or(AudioPlanResolved {
-        fps: 60.0,
-        segments: Vec::new(),
-    });
+    let plan = RENDER_AUDIO_PLAN
+        .lock()
+        .unwrap()
+        .clone()
+        .unwrap_or(AudioPlanResolved {
+            fps: 60.0,
+            segments: Vec::new(),
+        });
 
     (headers, Json(plan))
 }
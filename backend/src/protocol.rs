@@ -0,0 +1,218 @@
+//! Binary frame packet sent over `/ws`.
+//!
+//! Layout (little endian, `FRAME_HEADER_LEN` bytes followed by the pixel payload):
+//!
+//! | offset | size | field          |
+//! |--------|------|----------------|
+//! | 0      | 4    | magic `FSFR`   |
+//! | 4      | 2    | version        |
+//! | 6      | 2    | header length  |
+//! | 8      | 4    | width          |
+//! | 12     | 4    | height         |
+//! | 16     | 4    | stride (bytes) |
+//! | 20     | 1    | pixel format   |
+//! | 21     | 3    | reserved       |
+//! | 24     | 4    | flags          |
+//! | 28     | 4    | requested frame|
+//! | 32     | 4    | source frame   |
+//! | 36     | 8    | timestamp (µs) |
+//! | 44     | 4    | payload length |
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::DecodeError;
+
+pub const FRAME_MAGIC: [u8; 4] = *b"FSFR";
+pub const FRAME_VERSION: u16 = 1;
+pub const FRAME_HEADER_LEN: usize = 48;
+
+/// Timestamp value used when the presentation time of a frame is unknown.
+pub const TIMESTAMP_UNKNOWN: i64 = i64::MIN;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PixelFormat {
+    Rgba8 = 1,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> u32 {
+        match self {
+            PixelFormat::Rgba8 => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameFlags(u32);
+
+impl FrameFlags {
+    pub const NONE: FrameFlags = FrameFlags(0);
+    /// The requested frame was missing and an earlier frame was sent instead.
+    pub const SUBSTITUTED_PREVIOUS: FrameFlags = FrameFlags(1 << 0);
+    /// The payload is a generated fill, not decoded picture data.
+    pub const PLACEHOLDER: FrameFlags = FrameFlags(1 << 1);
+    /// Decoding failed; the payload is a placeholder.
+    pub const DECODE_ERROR: FrameFlags = FrameFlags(1 << 2);
+    /// Mixed from two frames for a remapped time; `source_frame` is the earlier one.
+    pub const BLENDED: FrameFlags = FrameFlags(1 << 3);
+
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub fn contains(self, other: FrameFlags) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl std::ops::BitOr for FrameFlags {
+    type Output = FrameFlags;
+
+    fn bitor(self, rhs: FrameFlags) -> FrameFlags {
+        FrameFlags(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for FrameFlags {
+    fn bitor_assign(&mut self, rhs: FrameFlags) {
+        self.0 |= rhs.0;
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FrameHeader {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_format: PixelFormat,
+    pub flags: FrameFlags,
+    pub requested_frame: u32,
+    pub source_frame: u32,
+    pub timestamp_us: i64,
+}
+
+impl FrameHeader {
+    pub fn stride(&self) -> u32 {
+        self.width * self.pixel_format.bytes_per_pixel()
+    }
+
+    /// Build a full packet: header followed by `payload`.
+    pub fn encode(&self, payload: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(FRAME_HEADER_LEN + payload.len());
+        packet.extend_from_slice(&FRAME_MAGIC);
+        packet.extend_from_slice(&FRAME_VERSION.to_le_bytes());
+        packet.extend_from_slice(&(FRAME_HEADER_LEN as u16).to_le_bytes());
+        packet.extend_from_slice(&self.width.to_le_bytes());
+        packet.extend_from_slice(&self.height.to_le_bytes());
+        packet.extend_from_slice(&self.stride().to_le_bytes());
+        packet.push(self.pixel_format as u8);
+        packet.extend_from_slice(&[0u8; 3]);
+        packet.extend_from_slice(&self.flags.bits().to_le_bytes());
+        packet.extend_from_slice(&self.requested_frame.to_le_bytes());
+        packet.extend_from_slice(&self.source_frame.to_le_bytes());
+        packet.extend_from_slice(&self.timestamp_us.to_le_bytes());
+        packet.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        packet.extend_from_slice(payload);
+        packet
+    }
+}
+
+/// JSON text messages sent over `/ws` next to the binary frame packets.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ServerMessage {
+    /// Decoding `frame` of `video` failed. Followed by a frame packet for the same request
+    /// with `DECODE_ERROR` set and an empty payload.
+    Error {
+        video: String,
+        frame: u32,
+        message: String,
+        error: DecodeError,
+    },
+}
+
+/// Presentation time in a frame request: seconds, or an exact rational.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(untagged)]
+pub enum MediaTime {
+    Seconds(f64),
+    Rational { num: i64, den: i64 },
+}
+
+impl MediaTime {
+    pub fn seconds(self) -> Option<f64> {
+        let seconds = match self {
+            MediaTime::Seconds(seconds) => seconds,
+            MediaTime::Rational { den: 0, .. } => return None,
+            MediaTime::Rational { num, den } => num as f64 / den as f64,
+        };
+        seconds.is_finite().then_some(seconds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header() -> FrameHeader {
+        FrameHeader {
+            width: 3,
+            height: 2,
+            pixel_format: PixelFormat::Rgba8,
+            flags: FrameFlags::SUBSTITUTED_PREVIOUS | FrameFlags::PLACEHOLDER,
+            requested_frame: 7,
+            source_frame: 6,
+            timestamp_us: -1_234_567,
+        }
+    }
+
+    fn u32_at(packet: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(packet[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn header_fields_are_at_their_offsets() {
+        let payload = vec![9u8; 24];
+        let packet = header().encode(&payload);
+
+        assert_eq!(packet.len(), FRAME_HEADER_LEN + payload.len());
+        assert_eq!(&packet[0..4], b"FSFR");
+        assert_eq!(u16::from_le_bytes([packet[4], packet[5]]), FRAME_VERSION);
+        assert_eq!(
+            u16::from_le_bytes([packet[6], packet[7]]) as usize,
+            FRAME_HEADER_LEN
+        );
+        assert_eq!(u32_at(&packet, 8), 3);
+        assert_eq!(u32_at(&packet, 12), 2);
+        assert_eq!(u32_at(&packet, 16), 12);
+        assert_eq!(packet[20], PixelFormat::Rgba8 as u8);
+        assert_eq!(&packet[21..24], &[0, 0, 0]);
+        assert_eq!(u32_at(&packet, 24), 0b11);
+        assert_eq!(u32_at(&packet, 28), 7);
+        assert_eq!(u32_at(&packet, 32), 6);
+        assert_eq!(
+            i64::from_le_bytes(packet[36..44].try_into().unwrap()),
+            -1_234_567
+        );
+        assert_eq!(u32_at(&packet, 44), 24);
+        assert_eq!(&packet[FRAME_HEADER_LEN..], &payload[..]);
+    }
+
+    #[test]
+    fn empty_payload_is_header_only() {
+        let packet = FrameHeader {
+            flags: FrameFlags::DECODE_ERROR,
+            timestamp_us: TIMESTAMP_UNKNOWN,
+            ..header()
+        }
+        .encode(&[]);
+
+        assert_eq!(packet.len(), FRAME_HEADER_LEN);
+        assert_eq!(u32_at(&packet, 24), FrameFlags::DECODE_ERROR.bits());
+        assert_eq!(
+            i64::from_le_bytes(packet[36..44].try_into().unwrap()),
+            i64::MIN
+        );
+        assert_eq!(u32_at(&packet, 44), 0);
+    }
+}
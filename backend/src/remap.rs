@@ -0,0 +1,421 @@
+//! Time remapping: which source time a clip shows at each point of its place on the timeline,
+//! and how a source is conformed to the project frame rate.
+
+use serde::Deserialize;
+
+use crate::ffmpeg::filter::{AlphaMode, MAX_INTERPOLATION};
+
+/// Maps time into a clip (seconds from its first frame on the timeline) to time in its source.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimeRemap {
+    #[serde(flatten)]
+    pub curve: RemapCurve,
+    /// How a source time between two frames is shown.
+    #[serde(default)]
+    pub blend: FrameBlend,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum RemapCurve {
+    /// `offset + t * speed`. A negative speed plays backwards from `offset`.
+    Speed {
+        speed: f64,
+        #[serde(default)]
+        offset: f64,
+    },
+    /// `from - t * speed`: plays backwards from `from`.
+    Reverse {
+        from: f64,
+        #[serde(default = "default_speed")]
+        speed: f64,
+    },
+    /// The frame at `time` for the whole clip.
+    Freeze { time: f64 },
+    /// Speed keyframes, interpolated linearly and integrated from `offset`. The first and last
+    /// speeds are held before and after the keyframes. No keyframes is normal speed.
+    Curve {
+        keyframes: Vec<SpeedKeyframe>,
+        #[serde(default)]
+        offset: f64,
+    },
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+/// Playback speed at `time` seconds into the clip.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct SpeedKeyframe {
+    pub time: f64,
+    pub speed: f64,
+}
+
+/// How a source time between two frames is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrameBlend {
+    /// The frame on screen at that time.
+    #[default]
+    None,
+    /// The two frames around it, mixed by how close each is.
+    Frames,
+    /// Motion-interpolated frames (`minterpolate`), as many per source frame as the slowest
+    /// speed needs.
+    Interpolate,
+}
+
+/// How a source whose frame rate differs from the project's is shown on project frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConformMode {
+    /// The source frame closest to each project frame. At a lower project rate video files are
+    /// resampled by ffmpeg's `fps` filter, so the frames no project frame lands on are dropped
+    /// right after decoding, before they are converted, scaled or cached.
+    #[default]
+    Nearest,
+    /// The two source frames around each project frame, mixed by how close each is.
+    Blend,
+    /// Motion-interpolated frames (`minterpolate`), when the project rate is higher.
+    Interpolate,
+}
+
+impl ConformMode {
+    /// Whether the decoder resamples the source to the project rate, which only drops frames
+    /// when the project rate is lower.
+    pub fn resamples(self, source_fps: f64, project_fps: f64) -> bool {
+        self == ConformMode::Nearest && project_fps < source_fps
+    }
+
+    /// Output frames per source frame to ask the decoder for, so every project frame has a
+    /// picture of its own. 0 unless interpolating up to a higher rate.
+    pub fn interpolation_factor(self, source_fps: f64, project_fps: f64) -> u32 {
+        if self != ConformMode::Interpolate || source_fps <= 0.0 || project_fps <= source_fps {
+            return 0;
+        }
+        ((project_fps / source_fps).ceil() as u32).clamp(2, MAX_INTERPOLATION)
+    }
+}
+
+/// A stretch of the clip played at one speed, for remapping audio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RemapPiece {
+    /// Start and length in project frames, from the start of the clip.
+    pub start_frame: i64,
+    pub frames: i64,
+    /// Lowest source time played, in seconds.
+    pub source_start: f64,
+    /// Average speed. Negative plays the source stretch backwards, 0 is a freeze.
+    pub speed: f64,
+}
+
+/// Longest stretch of a speed curve given one audio speed.
+const CURVE_PIECE_SECONDS: f64 = 0.25;
+
+fn finite(value: f64) -> f64 {
+    if value.is_finite() { value } else { 0.0 }
+}
+
+impl TimeRemap {
+    /// Source time shown `seconds` into the clip. Never before the start of the source.
+    pub fn source_time(&self, seconds: f64) -> f64 {
+        let t = finite(seconds).max(0.0);
+        let source = match &self.curve {
+            RemapCurve::Speed { speed, offset } => finite(*offset) + t * finite(*speed),
+            RemapCurve::Reverse { from, speed } => finite(*from) - t * finite(*speed),
+            RemapCurve::Freeze { time } => finite(*time),
+            RemapCurve::Curve { keyframes, offset } => {
+                finite(*offset) + integrate_speed(&sorted_keyframes(keyframes), t)
+            }
+        };
+        source.max(0.0)
+    }
+
+    /// Output frames per source frame to ask the decoder for: enough interpolated frames for
+    /// the slowest speed to get a new picture on every project frame. 0 unless blending by
+    /// interpolation.
+    pub fn interpolation_factor(&self) -> u32 {
+        if self.blend != FrameBlend::Interpolate {
+            return 0;
+        }
+        let slowest = match &self.curve {
+            RemapCurve::Speed { speed, .. } | RemapCurve::Reverse { speed, .. } => {
+                finite(*speed).abs()
+            }
+            RemapCurve::Freeze { .. } => 0.0,
+            RemapCurve::Curve { keyframes, .. } => keyframes
+                .iter()
+                .map(|keyframe| finite(keyframe.speed).abs())
+                .filter(|speed| *speed > 0.0)
+                .fold(1.0, f64::min),
+        };
+        if slowest <= 0.0 || slowest >= 1.0 {
+            return 0;
+        }
+        ((1.0 / slowest).ceil() as u32).clamp(2, MAX_INTERPOLATION)
+    }
+
+    /// Split `frames` project frames at `fps` into stretches of constant speed, so audio can
+    /// follow the remap with `atempo`. Speed curves are cut at their keyframes and every
+    /// `CURVE_PIECE_SECONDS`, each piece playing at its average speed.
+    pub fn pieces(&self, fps: f64, frames: i64) -> Vec<RemapPiece> {
+        let mut cuts = vec![0, frames.max(0)];
+        if let RemapCurve::Curve { keyframes, .. } = &self.curve {
+            let step = ((CURVE_PIECE_SECONDS * fps).round() as i64).max(1);
+            cuts.extend((step..frames).step_by(step as usize));
+            cuts.extend(
+                keyframes
+                    .iter()
+                    .map(|keyframe| (finite(keyframe.time) * fps).round() as i64)
+                    .filter(|frame| (1..frames).contains(frame)),
+            );
+        }
+        cuts.sort_unstable();
+        cuts.dedup();
+
+        cuts.windows(2)
+            .map(|cut| {
+                let (start, end) = (cut[0], cut[1]);
+                let from = self.source_time(start as f64 / fps);
+                let to = self.source_time(end as f64 / fps);
+                let frames = end - start;
+                RemapPiece {
+                    start_frame: start,
+                    frames,
+                    source_start: from.min(to),
+                    speed: (to - from) / (frames as f64 / fps),
+                }
+            })
+            .collect()
+    }
+}
+
+fn sorted_keyframes(keyframes: &[SpeedKeyframe]) -> Vec<SpeedKeyframe> {
+    let mut keyframes = keyframes
+        .iter()
+        .filter(|keyframe| keyframe.time.is_finite() && keyframe.speed.is_finite())
+        .copied()
+        .collect::<Vec<_>>();
+    keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+    keyframes
+}
+
+fn speed_at(keyframes: &[SpeedKeyframe], t: f64) -> f64 {
+    let after = keyframes.partition_point(|keyframe| keyframe.time <= t);
+    match (
+        after.checked_sub(1).map(|i| keyframes[i]),
+        keyframes.get(after),
+    ) {
+        (None, None) => 1.0,
+        (Some(before), None) => before.speed,
+        (None, Some(next)) => next.speed,
+        (Some(before), Some(next)) => {
+            let span = next.time - before.time;
+            before.speed + (next.speed - before.speed) * (t - before.time) / span
+        }
+    }
+}
+
+/// Source seconds played during the first `t` seconds of the clip. The speed is linear
+/// between keyframes, so a trapezoid per stretch is exact.
+fn integrate_speed(keyframes: &[SpeedKeyframe], t: f64) -> f64 {
+    let mut points = vec![0.0];
+    points.extend(
+        keyframes
+            .iter()
+            .map(|keyframe| keyframe.time)
+            .filter(|time| *time > 0.0 && *time < t),
+    );
+    points.push(t);
+
+    points
+        .windows(2)
+        .map(|span| {
+            let (a, b) = (span[0], span[1]);
+            (b - a) * (speed_at(keyframes, a) + speed_at(keyframes, b)) / 2.0
+        })
+        .sum()
+}
+
+/// Mix two RGBA frames of the same size, `weight` of the way from `a` to `b`. Straight
+/// alpha is mixed by coverage, so a transparent pixel adds no color.
+pub fn blend_frames(a: &[u8], b: &[u8], weight: f32, alpha: AlphaMode) -> Vec<u8> {
+    let weight = weight.clamp(0.0, 1.0);
+    let mix = |x: f32, y: f32| x + (y - x) * weight;
+
+    let mut out = Vec::with_capacity(a.len());
+    for (pa, pb) in a.chunks_exact(4).zip(b.chunks_exact(4)) {
+        let (alpha_a, alpha_b) = (pa[3] as f32, pb[3] as f32);
+        let alpha_out = mix(alpha_a, alpha_b);
+        for channel in 0..3 {
+            let (ca, cb) = (pa[channel] as f32, pb[channel] as f32);
+            let value = match alpha {
+                AlphaMode::Premultiplied => mix(ca, cb),
+                AlphaMode::Straight if alpha_out > 0.0 => {
+                    mix(ca * alpha_a, cb * alpha_b) / alpha_out
+                }
+                AlphaMode::Straight => 0.0,
+            };
+            out.push(value.round().clamp(0.0, 255.0) as u8);
+        }
+        out.push(alpha_out.round().clamp(0.0, 255.0) as u8);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remap(curve: RemapCurve) -> TimeRemap {
+        TimeRemap {
+            curve,
+            blend: FrameBlend::None,
+        }
+    }
+
+    fn keyframe(time: f64, speed: f64) -> SpeedKeyframe {
+        SpeedKeyframe { time, speed }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn speed_and_reverse_are_linear() {
+        let slow = remap(RemapCurve::Speed {
+            speed: 0.5,
+            offset: 1.0,
+        });
+        assert_close(slow.source_time(2.0), 2.0);
+
+        let reverse = remap(RemapCurve::Reverse {
+            from: 5.0,
+            speed: 2.0,
+        });
+        assert_close(reverse.source_time(1.0), 3.0);
+        // 音源の先頭より前には戻らない
+        assert_close(reverse.source_time(10.0), 0.0);
+    }
+
+    #[test]
+    fn freeze_holds_one_time() {
+        let freeze = remap(RemapCurve::Freeze { time: 1.25 });
+        assert_close(freeze.source_time(0.0), 1.25);
+        assert_close(freeze.source_time(30.0), 1.25);
+    }
+
+    #[test]
+    fn curve_integrates_linear_speed_between_keyframes() {
+        let curve = remap(RemapCurve::Curve {
+            keyframes: vec![keyframe(2.0, 3.0), keyframe(0.0, 1.0)],
+            offset: 0.5,
+        });
+        // 0〜2秒は速度1→3の台形、その後は最後の速度のまま
+        assert_close(curve.source_time(1.0), 0.5 + 1.5);
+        assert_close(curve.source_time(2.0), 0.5 + 4.0);
+        assert_close(curve.source_time(3.0), 0.5 + 7.0);
+    }
+
+    #[test]
+    fn curve_without_keyframes_is_normal_speed() {
+        let curve = remap(RemapCurve::Curve {
+            keyframes: Vec::new(),
+            offset: 2.0,
+        });
+        assert_close(curve.source_time(1.5), 3.5);
+    }
+
+    #[test]
+    fn pieces_report_the_lowest_source_time() {
+        let reverse = remap(RemapCurve::Reverse {
+            from: 4.0,
+            speed: 1.0,
+        });
+        let pieces = reverse.pieces(30.0, 60);
+        assert_eq!(pieces.len(), 1);
+        assert_eq!((pieces[0].start_frame, pieces[0].frames), (0, 60));
+        assert_close(pieces[0].source_start, 2.0);
+        assert_close(pieces[0].speed, -1.0);
+    }
+
+    #[test]
+    fn curve_pieces_are_cut_at_keyframes_and_quarter_seconds() {
+        let curve = remap(RemapCurve::Curve {
+            keyframes: vec![keyframe(0.0, 1.0), keyframe(0.1, 2.0)],
+            offset: 0.0,
+        });
+        let starts = curve
+            .pieces(40.0, 20)
+            .iter()
+            .map(|piece| piece.start_frame)
+            .collect::<Vec<_>>();
+        assert_eq!(starts, vec![0, 4, 10]);
+    }
+
+    #[test]
+    fn conform_interpolates_only_up_to_a_higher_rate() {
+        assert_eq!(ConformMode::Nearest.interpolation_factor(24.0, 60.0), 0);
+        assert_eq!(ConformMode::Blend.interpolation_factor(24.0, 60.0), 0);
+        assert_eq!(ConformMode::Interpolate.interpolation_factor(24.0, 60.0), 3);
+        assert_eq!(ConformMode::Interpolate.interpolation_factor(30.0, 60.0), 2);
+        assert_eq!(ConformMode::Interpolate.interpolation_factor(24.0, 25.0), 2);
+        assert_eq!(ConformMode::Interpolate.interpolation_factor(60.0, 30.0), 0);
+        assert_eq!(ConformMode::Interpolate.interpolation_factor(0.0, 30.0), 0);
+    }
+
+    #[test]
+    fn nearest_resamples_only_down_to_a_lower_rate() {
+        assert!(ConformMode::Nearest.resamples(60.0, 24.0));
+        assert!(!ConformMode::Nearest.resamples(24.0, 60.0));
+        assert!(!ConformMode::Nearest.resamples(30.0, 30.0));
+        assert!(!ConformMode::Blend.resamples(60.0, 24.0));
+        assert!(!ConformMode::Interpolate.resamples(60.0, 24.0));
+    }
+
+    #[test]
+    fn remap_interpolates_for_the_slowest_speed() {
+        let slow = TimeRemap {
+            curve: RemapCurve::Curve {
+                keyframes: vec![keyframe(0.0, 1.0), keyframe(1.0, 0.25)],
+                offset: 0.0,
+            },
+            blend: FrameBlend::Interpolate,
+        };
+        assert_eq!(slow.interpolation_factor(), 4);
+        assert_eq!(
+            remap(RemapCurve::Speed {
+                speed: 0.25,
+                offset: 0.0
+            })
+            .interpolation_factor(),
+            0
+        );
+    }
+
+    #[test]
+    fn blend_premultiplied_mixes_channels() {
+        let a = [200, 100, 0, 255];
+        let b = [0, 100, 200, 255];
+        assert_eq!(
+            blend_frames(&a, &b, 0.25, AlphaMode::Premultiplied),
+            vec![150, 100, 50, 255]
+        );
+    }
+
+    #[test]
+    fn blend_straight_ignores_the_color_of_transparent_pixels() {
+        let red = [255, 0, 0, 255];
+        let clear_blue = [0, 0, 255, 0];
+        assert_eq!(
+            blend_frames(&red, &clear_blue, 0.5, AlphaMode::Straight),
+            vec![255, 0, 0, 128]
+        );
+        assert_eq!(
+            blend_frames(&clear_blue, &clear_blue, 0.5, AlphaMode::Straight),
+            vec![0, 0, 0, 0]
+        );
+    }
+}
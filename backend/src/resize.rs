@@ -0,0 +1,359 @@
+//! Requested output sizes, scaled from the frames a clip is decoded at.
+
+use crate::ffmpeg::filter::{AlphaMode, FrameSize, ScaleMode};
+
+type Pixel = [f32; 4];
+
+/// Scale a `width` x `height` RGBA frame to `size`, fitting it like the ffmpeg filters do for
+/// each `ScaleMode`. Color is filtered premultiplied, so transparent pixels don't bleed into
+/// the edges; `alpha` says how color is stored in `rgba` and in the result.
+pub fn resize_frame(
+    rgba: &[u8],
+    (width, height): (u32, u32),
+    size: FrameSize,
+    alpha: AlphaMode,
+) -> Vec<u8> {
+    let (out_width, out_height) = (size.width, size.height);
+    if (width, height) == (out_width, out_height) {
+        return rgba.to_vec();
+    }
+    if out_width == 0 || out_height == 0 {
+        return Vec::new();
+    }
+    let (width, height) = (width.max(1), height.max(1));
+    let ratio_x = out_width as f64 / width as f64;
+    let ratio_y = out_height as f64 / height as f64;
+    let scaled = |ratio: f64| {
+        (
+            ((width as f64 * ratio).round() as u32).max(1),
+            ((height as f64 * ratio).round() as u32).max(1),
+        )
+    };
+
+    match size.scale {
+        ScaleMode::Stretch => resample(rgba, (width, height), (out_width, out_height), alpha),
+        ScaleMode::Contain { background } => {
+            let (inner_width, inner_height) = scaled(ratio_x.min(ratio_y));
+            let (inner_width, inner_height) =
+                (inner_width.min(out_width), inner_height.min(out_height));
+            let picture = resample(rgba, (width, height), (inner_width, inner_height), alpha);
+
+            let fill = match alpha {
+                AlphaMode::Straight => background.rgba(),
+                AlphaMode::Premultiplied => premultiply(background.rgba()),
+            };
+            let mut out = fill.repeat((out_width * out_height) as usize);
+            let (x, y) = (
+                (out_width - inner_width) / 2,
+                (out_height - inner_height) / 2,
+            );
+            copy_rect(
+                &picture,
+                inner_width,
+                (0, 0),
+                &mut out,
+                out_width,
+                (x, y),
+                (inner_width, inner_height),
+            );
+            out
+        }
+        ScaleMode::Cover { anchor } => {
+            let (scaled_width, scaled_height) = scaled(ratio_x.max(ratio_y));
+            let (scaled_width, scaled_height) =
+                (scaled_width.max(out_width), scaled_height.max(out_height));
+            let picture = resample(rgba, (width, height), (scaled_width, scaled_height), alpha);
+
+            let origin = anchor.origin((scaled_width - out_width, scaled_height - out_height));
+            let mut out = vec![0; (out_width * out_height * 4) as usize];
+            copy_rect(
+                &picture,
+                scaled_width,
+                origin,
+                &mut out,
+                out_width,
+                (0, 0),
+                (out_width, out_height),
+            );
+            out
+        }
+    }
+}
+
+fn premultiply([r, g, b, a]: [u8; 4]) -> [u8; 4] {
+    let scale = |c: u8| ((c as u32 * a as u32 + 127) / 255) as u8;
+    [scale(r), scale(g), scale(b), a]
+}
+
+/// Copy `size` pixels from `src` at `from` to `dst` at `to`. Widths are in pixels.
+fn copy_rect(
+    src: &[u8],
+    src_width: u32,
+    from: (u32, u32),
+    dst: &mut [u8],
+    dst_width: u32,
+    to: (u32, u32),
+    size: (u32, u32),
+) {
+    let row = size.0 as usize * 4;
+    for line in 0..size.1 {
+        let src_start = (((from.1 + line) * src_width + from.0) * 4) as usize;
+        let dst_start = (((to.1 + line) * dst_width + to.0) * 4) as usize;
+        dst[dst_start..dst_start + row].copy_from_slice(&src[src_start..src_start + row]);
+    }
+}
+
+/// Triangle filter taps for scaling `src` samples to `dst`, widened when shrinking so every
+/// source sample counts (like ffmpeg's `bilinear`).
+fn taps(src: u32, dst: u32) -> Vec<(usize, Vec<f32>)> {
+    let ratio = src as f64 / dst as f64;
+    let support = ratio.max(1.0);
+    let last_sample = src as usize - 1;
+    (0..dst)
+        .map(|i| {
+            let center = (i as f64 + 0.5) * ratio - 0.5;
+            let first = ((center - support).floor() + 1.0).max(0.0) as usize;
+            let first = first.min(last_sample);
+            let last =
+                (((center + support).ceil() - 1.0).max(0.0) as usize).clamp(first, last_sample);
+
+            let mut weights = (first..=last)
+                .map(|j| (1.0 - (j as f64 - center).abs() / support).max(0.0) as f32)
+                .collect::<Vec<_>>();
+            let total = weights.iter().sum::<f32>();
+            if total > 0.0 {
+                weights.iter_mut().for_each(|weight| *weight /= total);
+            } else {
+                weights = vec![1.0 / weights.len() as f32; weights.len()];
+            }
+            (first, weights)
+        })
+        .collect()
+}
+
+/// Scale to exactly `to`, ignoring the aspect ratio.
+fn resample(rgba: &[u8], from: (u32, u32), to: (u32, u32), alpha: AlphaMode) -> Vec<u8> {
+    let (width, height) = (from.0 as usize, from.1 as usize);
+    let (out_width, out_height) = (to.0 as usize, to.1 as usize);
+
+    let pixels = rgba
+        .chunks_exact(4)
+        .map(|p| {
+            let a = p[3] as f32;
+            let color = |c: u8| match alpha {
+                AlphaMode::Straight => c as f32 * a / 255.0,
+                AlphaMode::Premultiplied => c as f32,
+            };
+            [color(p[0]), color(p[1]), color(p[2]), a]
+        })
+        .collect::<Vec<Pixel>>();
+    if pixels.len() < width * height {
+        return vec![0; out_width * out_height * 4];
+    }
+
+    // 横方向、縦方向の順に縮める
+    let columns = taps(from.0, to.0);
+    let mut rows = vec![[0.0; 4]; out_width * height];
+    for y in 0..height {
+        let line = &pixels[y * width..(y + 1) * width];
+        for (x, (first, weights)) in columns.iter().enumerate() {
+            rows[y * out_width + x] =
+                weigh(weights.iter().zip(&line[*first..]).map(|(w, p)| (*w, p)));
+        }
+    }
+
+    let lines = taps(from.1, to.1);
+    let mut out = Vec::with_capacity(out_width * out_height * 4);
+    for (first, weights) in &lines {
+        for x in 0..out_width {
+            let [r, g, b, a] = weigh(
+                weights
+                    .iter()
+                    .enumerate()
+                    .map(|(i, w)| (*w, &rows[(first + i) * out_width + x])),
+            );
+            let a = a.clamp(0.0, 255.0);
+            let color = |c: f32| match alpha {
+                AlphaMode::Straight if a > 0.0 => c * 255.0 / a,
+                AlphaMode::Straight => 0.0,
+                AlphaMode::Premultiplied => c,
+            };
+            for c in [color(r), color(g), color(b), a] {
+                out.push(c.round().clamp(0.0, 255.0) as u8);
+            }
+        }
+    }
+    out
+}
+
+fn weigh<'a>(samples: impl Iterator<Item = (f32, &'a Pixel)>) -> Pixel {
+    let mut sum = [0.0; 4];
+    for (weight, pixel) in samples {
+        for (total, value) in sum.iter_mut().zip(pixel) {
+            *total += weight * value;
+        }
+    }
+    sum
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffmpeg::filter::{Anchor, Background};
+
+    fn size(width: u32, height: u32, scale: ScaleMode) -> FrameSize {
+        FrameSize {
+            width,
+            height,
+            scale,
+        }
+    }
+
+    fn pixels(colors: &[[u8; 4]]) -> Vec<u8> {
+        colors.concat()
+    }
+
+    #[test]
+    fn same_size_is_copied() {
+        let rgba = pixels(&[[1, 2, 3, 4], [5, 6, 7, 8]]);
+        let out = resize_frame(
+            &rgba,
+            (2, 1),
+            size(2, 1, ScaleMode::Stretch),
+            AlphaMode::Straight,
+        );
+        assert_eq!(out, rgba);
+    }
+
+    #[test]
+    fn uniform_color_stays_uniform() {
+        let rgba = [10, 20, 30, 255].repeat(16);
+        let out = resize_frame(
+            &rgba,
+            (4, 4),
+            size(2, 3, ScaleMode::Stretch),
+            AlphaMode::Straight,
+        );
+        assert_eq!(out, [10, 20, 30, 255].repeat(6));
+    }
+
+    #[test]
+    fn transparent_pixels_do_not_bleed_color() {
+        let rgba = pixels(&[[255, 0, 0, 255], [0, 255, 0, 0]]);
+        let straight = resize_frame(
+            &rgba,
+            (2, 1),
+            size(1, 1, ScaleMode::Stretch),
+            AlphaMode::Straight,
+        );
+        assert_eq!(straight, vec![255, 0, 0, 128]);
+
+        let premultiplied = pixels(&[[255, 0, 0, 255], [0, 0, 0, 0]]);
+        let out = resize_frame(
+            &premultiplied,
+            (2, 1),
+            size(1, 1, ScaleMode::Stretch),
+            AlphaMode::Premultiplied,
+        );
+        assert_eq!(out, vec![128, 0, 0, 128]);
+    }
+
+    #[test]
+    fn contain_letterboxes_with_the_background() {
+        let rgba = [255, 255, 255, 255].repeat(8);
+        let blue = Background::Rgba(0x0000ffff);
+        let out = resize_frame(
+            &rgba,
+            (4, 2),
+            size(2, 2, ScaleMode::Contain { background: blue }),
+            AlphaMode::Straight,
+        );
+        assert_eq!(
+            out,
+            pixels(&[
+                [255, 255, 255, 255],
+                [255, 255, 255, 255],
+                [0, 0, 255, 255],
+                [0, 0, 255, 255],
+            ])
+        );
+    }
+
+    #[test]
+    fn contain_premultiplies_the_background() {
+        let rgba = [255, 255, 255, 255].repeat(2);
+        let out = resize_frame(
+            &rgba,
+            (2, 1),
+            size(
+                2,
+                3,
+                ScaleMode::Contain {
+                    background: Background::Rgba(0xff000080),
+                },
+            ),
+            AlphaMode::Premultiplied,
+        );
+        assert_eq!(&out[..4], &[128, 0, 0, 128]);
+    }
+
+    #[test]
+    fn cover_crops_around_the_anchor() {
+        let rgba = pixels(&[
+            [1, 0, 0, 255],
+            [2, 0, 0, 255],
+            [3, 0, 0, 255],
+            [4, 0, 0, 255],
+        ]);
+        let crop = |anchor| {
+            resize_frame(
+                &rgba,
+                (4, 1),
+                size(2, 1, ScaleMode::Cover { anchor }),
+                AlphaMode::Straight,
+            )
+        };
+        assert_eq!(
+            crop(Anchor::Left),
+            pixels(&[[1, 0, 0, 255], [2, 0, 0, 255]])
+        );
+        assert_eq!(
+            crop(Anchor::Center),
+            pixels(&[[2, 0, 0, 255], [3, 0, 0, 255]])
+        );
+        assert_eq!(
+            crop(Anchor::Right),
+            pixels(&[[3, 0, 0, 255], [4, 0, 0, 255]])
+        );
+    }
+
+    #[test]
+    fn empty_output_size_gives_no_pixels() {
+        let rgba = [0u8; 16];
+        let out = resize_frame(
+            &rgba,
+            (2, 2),
+            size(0, 2, ScaleMode::Stretch),
+            AlphaMode::Straight,
+        );
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn covering_size_scales_the_source_uniformly() {
+        let request = size(1920, 1080, ScaleMode::Stretch);
+        assert_eq!(request.covering((3840, 2160)), (1920, 1080));
+        assert_eq!(request.covering((1280, 720)), (1920, 1080));
+        assert_eq!(request.covering((720, 720)), (1920, 1920));
+        assert_eq!(request.covering((0, 0)), (1920, 1080));
+
+        let contain = size(
+            1920,
+            1080,
+            ScaleMode::Contain {
+                background: Background::Transparent,
+            },
+        );
+        assert_eq!(contain.covering((1440, 1440)), (1080, 1080));
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    cmp::Reverse,
+    env, mem,
+    sync::{Arc, LazyLock, Mutex},
+    thread,
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::{sync::oneshot, task::JoinError};
+
+/// Limits how many decode jobs (each driving one ffmpeg process) run at once.
+pub static SCHEDULER: LazyLock<DecodeScheduler> = LazyLock::new(DecodeScheduler::new);
+
+/// Who is waiting for a decode. Higher classes get a free slot first.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Deserialize, Serialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DecodePriority {
+    Prefetch,
+    Render,
+    /// Studio scrubbing and preview.
+    #[default]
+    Interactive,
+}
+
+/// The priority of a submitted job, shared with whoever may need it sooner. Raising it moves
+/// the job up while it is still waiting for a slot.
+#[derive(Debug, Clone)]
+pub struct JobPriority(Arc<Mutex<DecodePriority>>);
+
+impl JobPriority {
+    pub fn new(priority: DecodePriority) -> Self {
+        Self(Arc::new(Mutex::new(priority)))
+    }
+
+    pub fn get(&self) -> DecodePriority {
+        *self.0.lock().unwrap()
+    }
+
+    /// Raise the priority to `priority`, unless it is already as high.
+    pub fn raise(&self, priority: DecodePriority) {
+        let mut current = self.0.lock().unwrap();
+        *current = (*current).max(priority);
+    }
+}
+
+impl From<DecodePriority> for JobPriority {
+    fn from(priority: DecodePriority) -> Self {
+        Self::new(priority)
+    }
+}
+
+/// Default limit: half the cores available to the process, since ffmpeg runs several threads
+/// of its own.
+fn default_max_running() -> usize {
+    if let Some(max) = env::var("FRAMESCRIPT_DECODE_PROCESSES")
+        .ok()
+        .and_then(|value| value.trim().parse::<usize>().ok())
+    {
+        return max.max(1);
+    }
+
+    thread::available_parallelism()
+        .map(|threads| threads.get() / 2)
+        .unwrap_or(2)
+        .max(1)
+}
+
+pub struct DecodeScheduler {
+    state: Mutex<SchedulerState>,
+}
+
+struct SchedulerState {
+    max_running: usize,
+    running: usize,
+    /// Unordered, since priorities can change while waiting; see `pop_waiter`.
+    waiting: Vec<Waiter>,
+    seq: u64,
+}
+
+struct Waiter {
+    priority: JobPriority,
+    seq: u64,
+    slot: oneshot::Sender<Slot>,
+}
+
+impl SchedulerState {
+    /// Take the waiter with the highest priority, the earliest among equals.
+    fn pop_waiter(&mut self) -> Option<Waiter> {
+        let next = self
+            .waiting
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, waiter)| (waiter.priority.get(), Reverse(waiter.seq)))
+            .map(|(index, _)| index)?;
+        Some(self.waiting.swap_remove(next))
+    }
+}
+
+/// A running slot. Dropping it hands the slot to the next waiter, or frees it.
+struct Slot {
+    scheduler: &'static DecodeScheduler,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.scheduler.release();
+    }
+}
+
+/// Snapshot for `GET /decoder_stats`.
+#[derive(Debug, Serialize)]
+pub struct SchedulerStats {
+    pub max_running: usize,
+    pub running: usize,
+    pub waiting: usize,
+}
+
+impl DecodeScheduler {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(SchedulerState {
+                max_running: default_max_running(),
+                running: 0,
+                waiting: Vec::new(),
+                seq: 0,
+            }),
+        }
+    }
+
+    /// Run `job` on the blocking pool once a slot is free. The slot is held until the job
+    /// returns, even if the caller stops waiting for it.
+    pub async fn run<T, F>(
+        &'static self,
+        priority: impl Into<JobPriority>,
+        job: F,
+    ) -> Result<T, JoinError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let priority = priority.into();
+        let slot = self.acquire(priority).await;
+        tokio::task::spawn_blocking(move || {
+            let _slot = slot;
+            job()
+        })
+        .await
+    }
+
+    async fn acquire(&'static self, priority: JobPriority) -> Slot {
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.running < state.max_running && state.waiting.is_empty() {
+                state.running += 1;
+                return Slot { scheduler: self };
+            }
+
+            let (sender, receiver) = oneshot::channel();
+            state.seq += 1;
+            let seq = state.seq;
+            state.waiting.push(Waiter {
+                priority,
+                seq,
+                slot: sender,
+            });
+            receiver
+        };
+
+        // 送信側は release でしか落とされないので、受信に失敗することはない
+        receiver.await.expect("scheduler dropped a waiter")
+    }
+
+    /// Give the slot of a finished job to the highest waiter, or free it.
+    fn release(&'static self) {
+        let mut state = self.state.lock().unwrap();
+        while state.running <= state.max_running {
+            let Some(waiter) = state.pop_waiter() else {
+                break;
+            };
+            match waiter.slot.send(Slot { scheduler: self }) {
+                Ok(()) => return,
+                // 待っていた側が既にキャンセルされている
+                Err(slot) => mem::forget(slot),
+            }
+        }
+        state.running -= 1;
+    }
+
+    pub fn set_max_running(&'static self, max: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.max_running = max.max(1);
+
+        // 上限を上げた分だけ待機中のジョブを起こす
+        while state.running < state.max_running {
+            let Some(waiter) = state.pop_waiter() else {
+                break;
+            };
+            state.running += 1;
+            if let Err(slot) = waiter.slot.send(Slot { scheduler: self }) {
+                mem::forget(slot);
+                state.running -= 1;
+            }
+        }
+    }
+
+    pub fn stats(&self) -> SchedulerStats {
+        let state = self.state.lock().unwrap();
+        SchedulerStats {
+            max_running: state.max_running,
+            running: state.running,
+            waiting: state.waiting.len(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+
+    /// A scheduler of its own with one slot, so tests don't share `SCHEDULER`.
+    fn scheduler() -> &'static DecodeScheduler {
+        let scheduler = Box::leak(Box::new(DecodeScheduler::new()));
+        scheduler.set_max_running(1);
+        scheduler
+    }
+
+    /// Queue one job per entry of `jobs` behind a held slot, then free it and return the
+    /// order the jobs ran in.
+    async fn run_order(jobs: &[(DecodePriority, &'static str)]) -> Vec<&'static str> {
+        let scheduler = scheduler();
+        let held = scheduler.acquire(DecodePriority::Interactive.into()).await;
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut handles = Vec::new();
+        for &(priority, name) in jobs {
+            let order = order.clone();
+            handles.push(tokio::spawn(async move {
+                scheduler
+                    .run(priority, move || order.lock().unwrap().push(name))
+                    .await
+                    .unwrap();
+            }));
+            // 次のジョブより先に待ち行列へ入れる
+            while scheduler.stats().waiting < handles.len() {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        drop(held);
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        Arc::try_unwrap(order).unwrap().into_inner().unwrap()
+    }
+
+    #[tokio::test]
+    async fn higher_priorities_get_the_slot_first() {
+        let order = run_order(&[
+            (DecodePriority::Prefetch, "prefetch"),
+            (DecodePriority::Render, "render"),
+            (DecodePriority::Interactive, "interactive"),
+        ])
+        .await;
+        assert_eq!(order, vec!["interactive", "render", "prefetch"]);
+    }
+
+    #[tokio::test]
+    async fn equal_priorities_run_in_arrival_order() {
+        let order = run_order(&[
+            (DecodePriority::Render, "first"),
+            (DecodePriority::Render, "second"),
+            (DecodePriority::Interactive, "urgent"),
+            (DecodePriority::Render, "third"),
+        ])
+        .await;
+        assert_eq!(order, vec!["urgent", "first", "second", "third"]);
+    }
+
+    #[tokio::test]
+    async fn a_raised_job_moves_up_while_it_waits() {
+        let scheduler = scheduler();
+        let held = scheduler.acquire(DecodePriority::Interactive.into()).await;
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let raised = JobPriority::new(DecodePriority::Prefetch);
+        let mut handles = Vec::new();
+        for (priority, name) in [
+            (raised.clone(), "raised"),
+            (DecodePriority::Render.into(), "render"),
+        ] {
+            let order = order.clone();
+            handles.push(tokio::spawn(async move {
+                scheduler
+                    .run(priority, move || order.lock().unwrap().push(name))
+                    .await
+                    .unwrap();
+            }));
+            while scheduler.stats().waiting < handles.len() {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        raised.raise(DecodePriority::Interactive);
+        // 下げる方向には変わらない
+        raised.raise(DecodePriority::Prefetch);
+        drop(held);
+        for handle in handles {
+            handle.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec!["raised", "render"]);
+    }
+
+    #[tokio::test]
+    async fn slots_are_freed_after_the_jobs() {
+        let scheduler = scheduler();
+        scheduler
+            .run(DecodePriority::Prefetch, || ())
+            .await
+            .unwrap();
+        let stats = scheduler.stats();
+        assert_eq!((stats.running, stats.waiting), (0, 0));
+    }
+}
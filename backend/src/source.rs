@@ -0,0 +1,205 @@
+mod cli;
+mod sequence;
+mod still;
+
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    error::DecodeError,
+    ffmpeg::{
+        filter::OutputParams,
+        index::media_index,
+        probe_has_video_stream, probe_stream_info,
+        stream::{MediaKind, StreamSelector},
+    },
+};
+
+pub use cli::FfmpegCliSource;
+pub use sequence::{
+    DEFAULT_SEQUENCE_FPS, GapMode, ImageSequence, ImageSequenceSource, SequenceOptions,
+    is_sequence_path,
+};
+pub use still::StillImageSource;
+
+/// Where a clip's frames come from. Requests can pick one explicitly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceKind {
+    /// `Sequence` for directories and `%04d` patterns; `Animated` or `Still` for image files
+    /// (by extension, then by frame count); `Ffmpeg` otherwise.
+    #[default]
+    Auto,
+    /// ffmpeg CLI with hardware decoding, falling back to software.
+    Ffmpeg,
+    /// ffmpeg CLI, software decoding only.
+    FfmpegSoftware,
+    /// A single picture shown for every frame. Animated images show their first frame.
+    Still,
+    /// Animated GIF / APNG / WebP / AVIF, timed by the delays stored in the file.
+    Animated,
+    /// Numbered image files, from a directory or a `%d` / `%0Nd` file pattern.
+    Sequence,
+}
+
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "png", "apng", "jpg", "jpeg", "webp", "avif", "gif", "bmp", "tif", "tiff",
+];
+
+/// Whether `path` is a still or animated image file, by extension.
+pub fn is_image_path(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|extension| IMAGE_EXTENSIONS.contains(&extension.as_str()))
+}
+
+/// Whether the image at `path` has more than one frame.
+pub fn is_animated_image(path: &str) -> bool {
+    media_index(path, 0).is_ok_and(|index| index.frame_count() > 1)
+}
+
+impl SourceKind {
+    fn resolve(self, path: &str) -> SourceKind {
+        match self {
+            SourceKind::Auto if is_sequence_path(path) => SourceKind::Sequence,
+            SourceKind::Auto if is_image_path(path) => {
+                if is_animated_image(path) {
+                    SourceKind::Animated
+                } else {
+                    SourceKind::Still
+                }
+            }
+            SourceKind::Auto => SourceKind::Ffmpeg,
+            kind => kind,
+        }
+    }
+
+    /// Check that `path` can be decoded this way and open it. `stream` picks the video
+    /// stream of multi-track files; images and sequences only have one. An `output` without
+    /// a size is decoded at the source's canonical size (`OutputParams::at_canonical_size`).
+    pub fn open(
+        self,
+        path: &str,
+        output: &OutputParams,
+        sequence: &SequenceOptions,
+        stream: &StreamSelector,
+    ) -> Result<Arc<dyn FrameSource>, DecodeError> {
+        let kind = self.resolve(path);
+        if kind != SourceKind::Sequence {
+            check_video_file(path)?;
+        }
+        let video_stream = || {
+            stream
+                .resolve(path, MediaKind::Video)
+                .map_err(ffprobe_error)?
+                .ok_or_else(|| DecodeError::NoVideoStream {
+                    path: path.to_string(),
+                })
+        };
+        let canonical = |stream: u32| {
+            let info = media_index(path, stream)
+                .map(|index| index.stream())
+                .or_else(|_| probe_stream_info(path, stream))
+                .map_err(ffprobe_error)?;
+            Ok::<_, DecodeError>(output.at_canonical_size(info))
+        };
+        let source: Arc<dyn FrameSource> = match kind {
+            SourceKind::Auto | SourceKind::Ffmpeg => {
+                let stream = video_stream()?;
+                let output = canonical(stream)?;
+                Arc::new(FfmpegCliSource::new(path, stream, &output, true))
+            }
+            SourceKind::FfmpegSoftware => {
+                let stream = video_stream()?;
+                let output = canonical(stream)?;
+                Arc::new(FfmpegCliSource::new(path, stream, &output, false))
+            }
+            SourceKind::Still => Arc::new(StillImageSource::new(path, &canonical(0)?)),
+            // フレームごとの表示時間はインデックスから取れるので、動画と同じ経路でよい
+            SourceKind::Animated => Arc::new(FfmpegCliSource::new(path, 0, &canonical(0)?, false)),
+            SourceKind::Sequence => Arc::new(ImageSequenceSource::open(path, output, sequence)?),
+        };
+        Ok(source)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SourceMetadata {
+    /// Number of frames, if known.
+    pub frame_count: Option<u32>,
+    pub fps: Option<f64>,
+    /// Every frame is the same picture, so only frame 0 needs decoding.
+    pub still: bool,
+    /// Size of the decoded frames.
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Decodes one clip at one size. The frame cache sits on top of this.
+pub trait FrameSource: fmt::Debug + Send + Sync {
+    fn metadata(&self) -> SourceMetadata;
+
+    /// The frame on screen at `seconds` from the first frame.
+    fn seek(&self, seconds: f64) -> Result<u32, DecodeError>;
+
+    /// Presentation time of `frame` in seconds from the first frame, if known.
+    fn frame_time(&self, frame: u32) -> Option<f64>;
+
+    /// Decode frames `start..=end` as output-sized RGBA. Frames the source does not have
+    /// (past the end, dropped by the decoder) are missing from the result. Stops with
+    /// `DecodeError::Cancelled` between frames once `cancel` is cancelled.
+    fn decode_range(
+        &self,
+        start: u32,
+        end: u32,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<(u32, Vec<u8>)>, DecodeError>;
+
+    /// The ffmpeg filter chain frames go through, as passed to `-vf`. Part of the disk cache
+    /// key, so changes to the filters don't read back stale frames.
+    fn filter_graph(&self) -> String;
+
+    /// The files frames are decoded from. Their paths, sizes and modification times are part
+    /// of the disk cache key, so replacing any of them doesn't read back stale frames.
+    fn files(&self) -> Vec<PathBuf>;
+
+    /// Whether the source honors `OutputParams::conform_fps`, counting its frames in project
+    /// frames. Others are conformed by picking source frames (see `CachedDecoder`).
+    fn resamples(&self) -> bool {
+        false
+    }
+
+    /// Release processes and buffers held between calls.
+    fn close(&self) {}
+}
+
+/// Check that `path` exists and has a video stream (still images count as one).
+fn check_video_file(path: &str) -> Result<(), DecodeError> {
+    if !Path::new(path).is_file() {
+        return Err(DecodeError::FileNotFound {
+            path: path.to_string(),
+        });
+    }
+    match probe_has_video_stream(path) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(DecodeError::NoVideoStream {
+            path: path.to_string(),
+        }),
+        Err(reason) => Err(ffprobe_error(reason)),
+    }
+}
+
+fn ffprobe_error(reason: String) -> DecodeError {
+    DecodeError::Ffmpeg {
+        reason: "ffprobe failed".to_string(),
+        status: None,
+        stderr: reason,
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex, OnceLock, Weak},
+};
+
+use tokio_util::sync::CancellationToken;
+use tracing::debug;
+
+use crate::{
+    error::DecodeError,
+    ffmpeg::{
+        StreamInfo,
+        filter::{FrameRate, OutputParams},
+        index::{MediaIndex, media_index},
+        probe_video_fps,
+        session::DecodeSession,
+    },
+    scheduler::SCHEDULER,
+    source::{FrameSource, SourceMetadata, ffprobe_error},
+};
+
+pub(super) type SessionSlot = Arc<Mutex<Option<DecodeSession>>>;
+
+/// Slots of every source that may hold a session, least recently used first.
+static OPEN_SESSIONS: Mutex<Vec<Weak<Mutex<Option<DecodeSession>>>>> = Mutex::new(Vec::new());
+
+/// Mark `slot` as just used, and close the least recently used sessions of other sources
+/// while more are open than the scheduler runs decode jobs at once. Sessions in use are
+/// skipped. Called with `slot` locked.
+pub(super) fn keep_open(slot: &SessionSlot) {
+    let mut open = OPEN_SESSIONS.lock().unwrap();
+    open.retain(|other| other.strong_count() > 0 && other.as_ptr() != Arc::as_ptr(slot));
+    open.push(Arc::downgrade(slot));
+
+    let max = SCHEDULER.stats().max_running;
+    let mut index = 0;
+    while open.len() > max && index + 1 < open.len() {
+        let closed = match open[index].upgrade() {
+            None => true,
+            Some(other) => match other.try_lock() {
+                Ok(mut session) => {
+                    if let Some(session) = session.take() {
+                        debug!(path = session.path(), "closing least recently used session");
+                    }
+                    true
+                }
+                Err(_) => false,
+            },
+        };
+        if closed {
+            open.remove(index);
+        } else {
+            index += 1;
+        }
+    }
+}
+
+/// Decodes through the ffmpeg CLI, keeping one streaming session open between calls.
+#[derive(Debug)]
+pub struct FfmpegCliSource {
+    path: String,
+    /// Position among the file's video streams.
+    video_stream: u32,
+    output: OutputParams,
+    use_hwaccel: bool,
+    fps: OnceLock<Option<f64>>,
+    /// Replaced only when the playhead jumps behind it or too far ahead, and closed when
+    /// other sources need the process (`keep_open`).
+    session: SessionSlot,
+}
+
+impl FfmpegCliSource {
+    pub fn new(path: &str, video_stream: u32, output: &OutputParams, use_hwaccel: bool) -> Self {
+        Self {
+            path: path.to_string(),
+            video_stream,
+            output: output.clone(),
+            use_hwaccel,
+            fps: OnceLock::new(),
+            session: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    fn index(&self) -> Result<Arc<MediaIndex>, DecodeError> {
+        media_index(&self.path, self.video_stream).map_err(ffprobe_error)
+    }
+
+    /// Frame rate of the source stream, before field-rate deinterlacing and interpolation.
+    fn fps(&self) -> Option<f64> {
+        *self
+            .fps
+            .get_or_init(|| probe_video_fps(&self.path, self.video_stream).ok())
+    }
+
+    /// Output frames per source frame.
+    fn subframes(&self) -> u32 {
+        self.output.frames_per_source_frame()
+    }
+
+    /// Project frame rate the frames are counted at, when resampled.
+    fn conform_fps(&self) -> Option<f64> {
+        self.output.conform_fps.map(|FrameRate(fps)| fps)
+    }
+
+    /// Project frames up to the end of the last source frame, when resampled to `fps`.
+    fn conformed_frame_count(&self, index: &MediaIndex, fps: f64) -> u32 {
+        let Some(last) = index.frame_count().checked_sub(1).map(|last| last as u32) else {
+            return 0;
+        };
+        let end =
+            index.time_of(last).unwrap_or(0.0) + self.frame_duration(index, last).unwrap_or(0.0);
+        ((end * fps - 1e-6).ceil() as u32).max(1)
+    }
+
+    /// How long source `frame` is shown, from the index or the frame rate.
+    fn frame_duration(&self, index: &MediaIndex, frame: u32) -> Option<f64> {
+        index
+            .frame_duration(frame)
+            .or_else(|| self.fps().map(|fps| 1.0 / fps))
+    }
+}
+
+impl FrameSource for FfmpegCliSource {
+    fn metadata(&self) -> SourceMetadata {
+        if let Some(fps) = self.conform_fps() {
+            return SourceMetadata {
+                frame_count: self
+                    .index()
+                    .ok()
+                    .map(|index| self.conformed_frame_count(&index, fps)),
+                fps: Some(fps),
+                still: false,
+                width: self.output.width,
+                height: self.output.height,
+            };
+        }
+        SourceMetadata {
+            frame_count: self
+                .index()
+                .ok()
+                .map(|index| index.frame_count() as u32 * self.subframes()),
+            fps: self.fps().map(|fps| fps * self.subframes() as f64),
+            still: false,
+            width: self.output.width,
+            height: self.output.height,
+        }
+    }
+
+    fn seek(&self, seconds: f64) -> Result<u32, DecodeError> {
+        let index = self.index()?;
+        if let Some(fps) = self.conform_fps() {
+            let count = self.conformed_frame_count(&index, fps);
+            if count == 0 {
+                return Err(DecodeError::NoVideoStream {
+                    path: self.path.clone(),
+                });
+            }
+            let frame = (seconds.max(0.0) * fps + 1e-6).floor() as u32;
+            return Ok(frame.min(count - 1));
+        }
+        let frame = index
+            .frame_at(seconds)
+            .ok_or_else(|| DecodeError::NoVideoStream {
+                path: self.path.clone(),
+            })?;
+        let subframes = self.subframes();
+        if subframes == 1 {
+            return Ok(frame);
+        }
+
+        // フィールドや補間フレームはフレームの表示時間を等分した位置から
+        const EPSILON: f64 = 1e-6;
+        let into = seconds - index.time_of(frame).unwrap_or(0.0);
+        let subframe = match self.frame_duration(&index, frame) {
+            Some(duration) if duration > 0.0 => ((into / duration * subframes as f64 + EPSILON)
+                .floor()
+                .max(0.0) as u32)
+                .min(subframes - 1),
+            _ => 0,
+        };
+        Ok(frame * subframes + subframe)
+    }
+
+    fn frame_time(&self, frame: u32) -> Option<f64> {
+        if let Some(fps) = self.conform_fps() {
+            return Some(frame as f64 / fps);
+        }
+        let subframes = self.subframes();
+        let (source_frame, subframe) = (frame / subframes, frame % subframes);
+        let index = self.index().ok();
+        let Some(seconds) = index.as_ref().and_then(|index| index.time_of(source_frame)) else {
+            return self
+                .fps()
+                .map(|fps| frame as f64 / (fps * subframes as f64));
+        };
+        if subframe == 0 {
+            return Some(seconds);
+        }
+        let duration = index.and_then(|index| self.frame_duration(&index, source_frame))?;
+        Some(seconds + duration * subframe as f64 / subframes as f64)
+    }
+
+    fn decode_range(
+        &self,
+        start: u32,
+        end: u32,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<(u32, Vec<u8>)>, DecodeError> {
+        // 終端より後ろのためにセッションを開かない
+        let end = match self.metadata().frame_count {
+            Some(count) if start >= count => return Ok(Vec::new()),
+            Some(count) => end.min(count - 1),
+            None => end,
+        };
+        let mut session = self.session.lock().unwrap();
+
+        let session = match session.take() {
+            Some(current) if current.can_reach(start as usize) => session.insert(current),
+            _ => session.insert(DecodeSession::open(
+                &self.path,
+                self.video_stream,
+                start as usize,
+                &self.output,
+                self.use_hwaccel,
+            )?),
+        };
+
+        keep_open(&self.session);
+
+        let frames = session.read_range(start as usize, end as usize, cancel)?;
+        Ok(frames
+            .into_iter()
+            .map(|(index, rgba)| (index as u32, rgba))
+            .collect())
+    }
+
+    fn filter_graph(&self) -> String {
+        let stream = self
+            .index()
+            .map(|index| index.stream())
+            .unwrap_or(StreamInfo {
+                index: self.video_stream,
+                ..Default::default()
+            });
+        self.output.filters(stream).join(",")
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(&self.path)]
+    }
+
+    fn resamples(&self) -> bool {
+        true
+    }
+
+    fn close(&self) {
+        self.session.lock().unwrap().take();
+    }
+}
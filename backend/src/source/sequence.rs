@@ -0,0 +1,713 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs,
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use serde::Deserialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    error::DecodeError,
+    ffmpeg::{
+        StreamInfo, command::SequenceInput, deinterlace::Deinterlace, filter::OutputParams,
+        probe_stream_info, session::DecodeSession,
+    },
+    source::{
+        FrameSource, SourceMetadata,
+        cli::{SessionSlot, keep_open},
+    },
+};
+
+/// Image files carry no frame rate, so sequences play at this rate unless told otherwise.
+pub const DEFAULT_SEQUENCE_FPS: f64 = 24.0;
+
+const SEQUENCE_EXTENSIONS: &[&str] = &[
+    "png", "jpg", "jpeg", "exr", "dpx", "tif", "tiff", "tga", "bmp", "webp",
+];
+
+/// How an image sequence is read. All fields are optional and detected from the files.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct SequenceOptions {
+    /// Frames per second. Defaults to `DEFAULT_SEQUENCE_FPS`.
+    #[serde(default)]
+    pub fps: Option<f64>,
+    /// File number of frame 0. Defaults to the lowest number found.
+    #[serde(default)]
+    pub start_number: Option<u32>,
+    #[serde(default)]
+    pub gaps: GapMode,
+}
+
+impl PartialEq for SequenceOptions {
+    fn eq(&self, other: &Self) -> bool {
+        self.fps.map(f64::to_bits) == other.fps.map(f64::to_bits)
+            && self.start_number == other.start_number
+            && self.gaps == other.gaps
+    }
+}
+
+impl Eq for SequenceOptions {}
+
+impl Hash for SequenceOptions {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.fps.map(f64::to_bits).hash(state);
+        self.start_number.hash(state);
+        self.gaps.hash(state);
+    }
+}
+
+/// What a missing number in the sequence means.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GapMode {
+    /// Frames follow the file numbers; a missing file holds the previous image.
+    #[default]
+    Hold,
+    /// Frames are the existing files in order.
+    Skip,
+}
+
+/// Whether `path` names an image sequence: a directory, or a file pattern like
+/// `shot_%04d.png` (unless a file with that literal name exists).
+pub fn is_sequence_path(path: &str) -> bool {
+    let path_ref = Path::new(path);
+    path_ref.is_dir() || (!path_ref.is_file() && parse_pattern(path).is_some())
+}
+
+/// The files of one sequence, in frame order.
+#[derive(Debug)]
+pub struct ImageSequence {
+    dir: PathBuf,
+    pattern: Pattern,
+    /// File number of each frame; `None` for a gap that holds the previous image.
+    frames: Vec<Option<u32>>,
+    fps: f64,
+    /// Probed from the first file; every file is assumed to be alike.
+    stream: StreamInfo,
+}
+
+impl ImageSequence {
+    pub fn scan(path: &str, options: &SequenceOptions) -> Result<Self, DecodeError> {
+        let not_found = || DecodeError::FileNotFound {
+            path: path.to_string(),
+        };
+
+        let (dir, pattern, files) = if Path::new(path).is_dir() {
+            let (pattern, files) = scan_directory(Path::new(path)).ok_or_else(not_found)?;
+            (PathBuf::from(path), pattern, files)
+        } else {
+            let (dir, pattern) = parse_pattern(path).ok_or_else(not_found)?;
+            let files = list_numbered(&dir)
+                .into_iter()
+                .filter_map(|(name, _)| pattern.number(&name))
+                .collect();
+            (dir, pattern, files)
+        };
+
+        let start = options
+            .start_number
+            .or_else(|| files.first().copied())
+            .ok_or_else(not_found)?;
+        let frames: Vec<_> = match options.gaps {
+            GapMode::Hold => {
+                let last = files.last().copied().unwrap_or(0);
+                (start..=last)
+                    .map(|number| files.contains(&number).then_some(number))
+                    .collect()
+            }
+            GapMode::Skip => files.range(start..).map(|number| Some(*number)).collect(),
+        };
+        // 先頭は必ず実在するファイルにする (ホールドする画像が無いため)
+        if !matches!(frames.first(), Some(Some(_))) {
+            return Err(not_found());
+        }
+
+        let fps = options
+            .fps
+            .filter(|fps| fps.is_finite() && *fps > 0.0)
+            .unwrap_or(DEFAULT_SEQUENCE_FPS);
+        let mut sequence = Self {
+            dir,
+            pattern,
+            frames,
+            fps,
+            stream: StreamInfo::default(),
+        };
+        sequence.stream = sequence
+            .file_for(0)
+            .and_then(|file| probe_stream_info(&file.to_string_lossy(), 0).ok())
+            .unwrap_or_default();
+        Ok(sequence)
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        self.frames.len() as u32
+    }
+
+    pub fn fps(&self) -> f64 {
+        self.fps
+    }
+
+    pub fn stream(&self) -> StreamInfo {
+        self.stream
+    }
+
+    /// The file shown at `frame`, following held gaps back to the image they hold.
+    fn file_for(&self, frame: u32) -> Option<PathBuf> {
+        let shown = self.shown_frame(frame)?;
+        let number = self.frames[shown as usize]?;
+        Some(self.dir.join(self.pattern.file_name(number)))
+    }
+
+    /// The frame whose file is shown at `frame`: `frame` itself, or the frame a gap holds.
+    fn shown_frame(&self, frame: u32) -> Option<u32> {
+        let frames = self.frames.get(..=frame as usize)?;
+        frames
+            .iter()
+            .rposition(Option::is_some)
+            .map(|shown| shown as u32)
+    }
+
+    /// The last frame from `frame` on that reads on from the file before it (see
+    /// `continues`), or that holds the same image, whichever `frame` starts.
+    fn run_end(&self, frame: u32) -> u32 {
+        let held = self.frames[frame as usize].is_none();
+        let mut end = frame;
+        while end + 1 < self.frame_count()
+            && if held {
+                self.frames[end as usize + 1].is_none()
+            } else {
+                self.continues(end + 1)
+            }
+        {
+            end += 1;
+        }
+        end
+    }
+
+    /// Whether the file of `frame` has the number after the file of the frame before it, so
+    /// ffmpeg reads the two in a row.
+    fn continues(&self, frame: u32) -> bool {
+        let number = |frame: u32| self.frames.get(frame as usize).copied().flatten();
+        frame > 0
+            && matches!(
+                (number(frame - 1), number(frame)),
+                (Some(previous), Some(number)) if previous.checked_add(1) == Some(number)
+            )
+    }
+
+    /// Every file of the sequence, in frame order.
+    fn files(&self) -> impl Iterator<Item = PathBuf> {
+        self.frames
+            .iter()
+            .flatten()
+            .map(|number| self.dir.join(self.pattern.file_name(*number)))
+    }
+
+    /// The pattern ffmpeg reads the files with.
+    fn input_pattern(&self) -> String {
+        self.dir
+            .join(self.pattern.ffmpeg_pattern())
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+/// A `%d` / `%0Nd` placeholder in a file name.
+#[derive(Debug)]
+struct Pattern {
+    prefix: String,
+    suffix: String,
+    width: Option<usize>,
+}
+
+impl Pattern {
+    /// The number of the file `name`, if ffmpeg would read it for that number.
+    fn number(&self, name: &str) -> Option<u32> {
+        let digits = name
+            .strip_prefix(&self.prefix)?
+            .strip_suffix(&self.suffix)?;
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        // ffmpeg は番号からファイル名を作るので、同じ書き方のものだけ一致させる
+        let number = digits.parse().ok()?;
+        (self.file_name(number) == name).then_some(number)
+    }
+
+    /// The file name of `number`, zero-padded to the width like `printf`.
+    fn file_name(&self, number: u32) -> String {
+        let width = self.width.unwrap_or(0);
+        format!("{}{number:0width$}{}", self.prefix, self.suffix)
+    }
+
+    /// The pattern as ffmpeg's image2 demuxer takes it, with `%` in the name escaped.
+    fn ffmpeg_pattern(&self) -> String {
+        let placeholder = match self.width {
+            Some(width) => format!("%0{width}d"),
+            None => "%d".to_string(),
+        };
+        format!(
+            "{}{placeholder}{}",
+            self.prefix.replace('%', "%%"),
+            self.suffix.replace('%', "%%")
+        )
+    }
+}
+
+fn parse_pattern(path: &str) -> Option<(PathBuf, Pattern)> {
+    let path = Path::new(path);
+    let name = path.file_name()?.to_str()?;
+    let (prefix, rest) = name.split_once('%')?;
+    let (spec, suffix) = rest.split_once('d')?;
+    let width = match spec {
+        "" => None,
+        spec if spec.starts_with('0') => Some(spec.parse::<usize>().ok()?),
+        _ => return None,
+    };
+    let dir = path.parent().unwrap_or(Path::new(".")).to_path_buf();
+    Some((
+        dir,
+        Pattern {
+            prefix: prefix.to_string(),
+            suffix: suffix.to_string(),
+            width,
+        },
+    ))
+}
+
+/// Image files in `dir`, by name.
+fn list_numbered(dir: &Path) -> Vec<(String, PathBuf)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+        .filter_map(|entry| {
+            let name = entry.file_name().to_str()?.to_string();
+            let extension = Path::new(&name).extension()?.to_str()?.to_ascii_lowercase();
+            SEQUENCE_EXTENSIONS
+                .contains(&extension.as_str())
+                .then(|| (name, entry.path()))
+        })
+        .collect()
+}
+
+/// Use the largest group of files that differ only in their last run of digits, with the
+/// pattern ffmpeg reads them by.
+fn scan_directory(dir: &Path) -> Option<(Pattern, BTreeSet<u32>)> {
+    let mut groups: HashMap<(String, String), Vec<(String, String)>> = HashMap::new();
+    for (name, _) in list_numbered(dir) {
+        let Some(end) = name.rfind(|c: char| c.is_ascii_digit()).map(|i| i + 1) else {
+            continue;
+        };
+        let start = name[..end]
+            .rfind(|c: char| !c.is_ascii_digit())
+            .map_or(0, |i| i + 1);
+        let digits = name[start..end].to_string();
+        groups
+            .entry((name[..start].to_string(), name[end..].to_string()))
+            .or_default()
+            .push((digits, name));
+    }
+
+    groups
+        .into_iter()
+        .map(|((prefix, suffix), names)| {
+            // ゼロ埋めされた番号があれば、その桁数で埋めたパターンにする
+            let width = names
+                .iter()
+                .map(|(digits, _)| digits)
+                .filter(|digits| digits.len() > 1 && digits.starts_with('0'))
+                .map(String::len)
+                .min();
+            let pattern = Pattern {
+                prefix,
+                suffix,
+                width,
+            };
+            let numbers: BTreeSet<u32> = names
+                .iter()
+                .filter_map(|(_, name)| pattern.number(name))
+                .collect();
+            (pattern, numbers)
+        })
+        .max_by(|(a_pattern, a), (b_pattern, b)| {
+            a.len().cmp(&b.len()).then_with(|| {
+                (&b_pattern.prefix, &b_pattern.suffix).cmp(&(&a_pattern.prefix, &a_pattern.suffix))
+            })
+        })
+}
+
+/// Numbered image files played as a clip. Runs of consecutively numbered files are read by
+/// one ffmpeg process each, through the image2 demuxer.
+#[derive(Debug)]
+pub struct ImageSequenceSource {
+    path: String,
+    output: OutputParams,
+    sequence: ImageSequence,
+    /// Continued while reads follow on from it, and closed when other sources need the
+    /// process (`keep_open`).
+    session: SessionSlot,
+    /// The last frame decoded, reused for held gaps.
+    last: Mutex<Option<(u32, Arc<Vec<u8>>)>>,
+}
+
+impl ImageSequenceSource {
+    pub fn open(
+        path: &str,
+        output: &OutputParams,
+        options: &SequenceOptions,
+    ) -> Result<Self, DecodeError> {
+        let sequence = ImageSequence::scan(path, options)?;
+        // 画像1枚が1フレームなので、フィールドごとの出力や補間は掛けない
+        let output = OutputParams {
+            deinterlace: match output.deinterlace {
+                Deinterlace::Double => Deinterlace::Auto,
+                mode => mode,
+            },
+            interpolate: 0,
+            ..output.at_canonical_size(sequence.stream())
+        };
+        Ok(Self {
+            path: path.to_string(),
+            output,
+            sequence,
+            session: Arc::new(Mutex::new(None)),
+            last: Mutex::new(None),
+        })
+    }
+
+    /// Decode frames `start..=end`, which have consecutively numbered files. Continues the
+    /// open session if it stopped right before `start`; otherwise starts one at `start`, since
+    /// seeking a sequence only takes a different start number.
+    fn read_run(
+        &self,
+        start: u32,
+        end: u32,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<(u32, Vec<u8>)>, DecodeError> {
+        let mut session = self.session.lock().unwrap();
+        let session = match session.take() {
+            Some(current)
+                if current.can_reach(start as usize)
+                    && current.next_frame() == start as usize
+                    && self.sequence.continues(start) =>
+            {
+                session.insert(current)
+            }
+            _ => {
+                let input = SequenceInput {
+                    start_number: self.sequence.frames[start as usize].ok_or_else(|| {
+                        DecodeError::FileNotFound {
+                            path: self.path.clone(),
+                        }
+                    })?,
+                    fps: self.sequence.fps(),
+                };
+                session.insert(DecodeSession::open_sequence(
+                    &self.sequence.input_pattern(),
+                    input,
+                    start as usize,
+                    &self.output,
+                    self.sequence.stream(),
+                )?)
+            }
+        };
+
+        keep_open(&self.session);
+
+        let frames: Vec<_> = session
+            .read_range(start as usize, end as usize, cancel)?
+            .into_iter()
+            .map(|(index, rgba)| (index as u32, rgba))
+            .collect();
+        if let Some((frame, rgba)) = frames.last() {
+            *self.last.lock().unwrap() = Some((*frame, Arc::new(rgba.clone())));
+        }
+        Ok(frames)
+    }
+
+    /// The image of `frame`, which has its own file, for the gap after it.
+    fn held_image(
+        &self,
+        frame: u32,
+        cancel: &CancellationToken,
+    ) -> Result<Option<Arc<Vec<u8>>>, DecodeError> {
+        if let Some((last_frame, rgba)) = self.last.lock().unwrap().as_ref()
+            && *last_frame == frame
+        {
+            return Ok(Some(rgba.clone()));
+        }
+        self.read_run(frame, frame, cancel)?;
+        Ok(self
+            .last
+            .lock()
+            .unwrap()
+            .as_ref()
+            .filter(|(last_frame, _)| *last_frame == frame)
+            .map(|(_, rgba)| rgba.clone()))
+    }
+}
+
+impl FrameSource for ImageSequenceSource {
+    fn metadata(&self) -> SourceMetadata {
+        SourceMetadata {
+            frame_count: Some(self.sequence.frame_count()),
+            fps: Some(self.sequence.fps()),
+            still: false,
+            width: self.output.width,
+            height: self.output.height,
+        }
+    }
+
+    fn seek(&self, seconds: f64) -> Result<u32, DecodeError> {
+        if self.sequence.frame_count() == 0 {
+            return Err(DecodeError::FileNotFound {
+                path: self.path.clone(),
+            });
+        }
+        let frame = (seconds.max(0.0) * self.sequence.fps() + 1e-6).floor() as u32;
+        Ok(frame.min(self.sequence.frame_count() - 1))
+    }
+
+    fn frame_time(&self, frame: u32) -> Option<f64> {
+        Some(frame as f64 / self.sequence.fps())
+    }
+
+    fn filter_graph(&self) -> String {
+        self.output.filters(self.sequence.stream()).join(",")
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        self.sequence.files().collect()
+    }
+
+    fn decode_range(
+        &self,
+        start: u32,
+        end: u32,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<(u32, Vec<u8>)>, DecodeError> {
+        let Some(last) = self.sequence.frame_count().checked_sub(1) else {
+            return Ok(Vec::new());
+        };
+        let end = end.min(last);
+        let mut frames = Vec::new();
+        let mut frame = start;
+        while frame <= end {
+            if cancel.is_cancelled() {
+                return Err(DecodeError::Cancelled);
+            }
+            let Some(shown) = self.sequence.shown_frame(frame) else {
+                break;
+            };
+            let run_end = self.sequence.run_end(frame).min(end);
+            if shown == frame {
+                let run = self.read_run(frame, run_end, cancel)?;
+                // 途中のファイルが消えていたら、そこで打ち切る
+                if run.last().map(|(index, _)| *index) != Some(run_end) {
+                    frames.extend(run);
+                    break;
+                }
+                frames.extend(run);
+            } else {
+                let Some(rgba) = self.held_image(shown, cancel)? else {
+                    break;
+                };
+                frames.extend((frame..=run_end).map(|index| (index, rgba.as_ref().clone())));
+            }
+            frame = run_end + 1;
+        }
+        Ok(frames)
+    }
+
+    fn close(&self) {
+        self.session.lock().unwrap().take();
+        self.last.lock().unwrap().take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory under the system temp dir holding empty files named `names`.
+    fn sequence_dir(test: &str, names: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "framescript-sequence-{test}-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in names {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        dir
+    }
+
+    fn file_names(sequence: &ImageSequence) -> Vec<Option<String>> {
+        sequence
+            .frames
+            .iter()
+            .map(|number| number.map(|number| sequence.pattern.file_name(number)))
+            .collect()
+    }
+
+    #[test]
+    fn pattern_parses_padded_and_plain_placeholders() {
+        let (dir, pattern) = parse_pattern("/shots/shot_%04d.png").unwrap();
+        assert_eq!(dir, PathBuf::from("/shots"));
+        assert_eq!(
+            (pattern.prefix.as_str(), pattern.suffix.as_str()),
+            ("shot_", ".png")
+        );
+        assert_eq!(pattern.width, Some(4));
+
+        let (_, pattern) = parse_pattern("frame%d.exr").unwrap();
+        assert_eq!(pattern.width, None);
+
+        assert!(parse_pattern("shot_%4d.png").is_none());
+        assert!(parse_pattern("shot_0001.png").is_none());
+    }
+
+    #[test]
+    fn padded_pattern_matches_like_ffmpeg() {
+        let (_, pattern) = parse_pattern("shot_%04d.png").unwrap();
+        assert_eq!(pattern.number("shot_0012.png"), Some(12));
+        assert_eq!(pattern.number("shot_12345.png"), Some(12345));
+        assert_eq!(pattern.number("shot_12.png"), None);
+        assert_eq!(pattern.number("shot_01234.png"), None);
+        assert_eq!(pattern.number("shot_00a1.png"), None);
+        assert_eq!(pattern.number("take_0012.png"), None);
+
+        let (_, plain) = parse_pattern("shot_%d.png").unwrap();
+        assert_eq!(plain.number("shot_7.png"), Some(7));
+        // ffmpeg は `%d` をゼロ埋めしないので、ゼロ埋めされたファイルは読まれない
+        assert_eq!(plain.number("shot_007.png"), None);
+    }
+
+    #[test]
+    fn directory_uses_the_largest_numbered_group() {
+        let dir = sequence_dir(
+            "groups",
+            &[
+                "a_001.png",
+                "a_002.png",
+                "a_004.png",
+                "b_1.png",
+                "notes.txt",
+            ],
+        );
+        let (pattern, numbers) = scan_directory(&dir).unwrap();
+        assert_eq!(numbers.into_iter().collect::<Vec<_>>(), vec![1, 2, 4]);
+        assert_eq!(pattern.ffmpeg_pattern(), "a_%03d.png");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gaps_hold_or_skip() {
+        let dir = sequence_dir("gaps", &["a_001.png", "a_002.png", "a_004.png"]);
+        let path = dir.to_string_lossy().into_owned();
+
+        let held = ImageSequence::scan(&path, &SequenceOptions::default()).unwrap();
+        assert_eq!(
+            file_names(&held),
+            vec![
+                Some("a_001.png".to_string()),
+                Some("a_002.png".to_string()),
+                None,
+                Some("a_004.png".to_string()),
+            ]
+        );
+        assert!(held.file_for(2).unwrap().ends_with("a_002.png"));
+        assert_eq!(held.fps(), DEFAULT_SEQUENCE_FPS);
+
+        let skipped = ImageSequence::scan(
+            &path,
+            &SequenceOptions {
+                gaps: GapMode::Skip,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(skipped.frame_count(), 3);
+        assert!(skipped.file_for(2).unwrap().ends_with("a_004.png"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn runs_end_where_ffmpeg_stops_reading() {
+        let dir = sequence_dir(
+            "runs",
+            &["a_001.png", "a_002.png", "a_005.png", "a_006.png"],
+        );
+        let path = dir.to_string_lossy().into_owned();
+
+        let held = ImageSequence::scan(&path, &SequenceOptions::default()).unwrap();
+        assert_eq!(held.run_end(0), 1);
+        assert_eq!(held.run_end(2), 3);
+        assert_eq!(held.shown_frame(3), Some(1));
+        assert_eq!(held.run_end(4), 5);
+        assert!(held.continues(1) && !held.continues(4));
+
+        let skipped = ImageSequence::scan(
+            &path,
+            &SequenceOptions {
+                gaps: GapMode::Skip,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(skipped.run_end(0), 1);
+        assert!(!skipped.continues(2));
+        assert_eq!(skipped.run_end(2), 3);
+        assert!(skipped.input_pattern().ends_with("a_%03d.png"));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ffmpeg_pattern_escapes_percent_signs() {
+        let pattern = Pattern {
+            prefix: "100%_".to_string(),
+            suffix: ".png".to_string(),
+            width: Some(4),
+        };
+        assert_eq!(pattern.ffmpeg_pattern(), "100%%_%04d.png");
+        assert_eq!(pattern.file_name(12), "100%_0012.png");
+    }
+
+    #[test]
+    fn pattern_scan_starts_at_start_number() {
+        let dir = sequence_dir("start", &["s_0001.png", "s_0002.png", "s_0003.png"]);
+        let path = dir.join("s_%04d.png").to_string_lossy().into_owned();
+
+        let sequence = ImageSequence::scan(
+            &path,
+            &SequenceOptions {
+                start_number: Some(2),
+                fps: Some(30.0),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(sequence.frame_count(), 2);
+        assert!(sequence.file_for(0).unwrap().ends_with("s_0002.png"));
+        assert_eq!(sequence.fps(), 30.0);
+
+        // 先頭が欠けていたらホールドする画像が無い
+        let missing = ImageSequence::scan(
+            &path,
+            &SequenceOptions {
+                start_number: Some(0),
+                ..Default::default()
+            },
+        );
+        assert!(matches!(missing, Err(DecodeError::FileNotFound { .. })));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
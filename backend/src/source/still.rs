@@ -0,0 +1,94 @@
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    error::DecodeError,
+    ffmpeg::{filter::OutputParams, index::media_index, session::DecodeSession},
+    source::{FrameSource, SourceMetadata},
+};
+
+/// A single image shown for every frame. Decoded once through the ffmpeg CLI.
+#[derive(Debug)]
+pub struct StillImageSource {
+    path: String,
+    output: OutputParams,
+    picture: Mutex<Option<Arc<Vec<u8>>>>,
+}
+
+impl StillImageSource {
+    pub fn new(path: &str, output: &OutputParams) -> Self {
+        Self {
+            path: path.to_string(),
+            output: output.clone(),
+            picture: Mutex::new(None),
+        }
+    }
+
+    fn picture(&self, cancel: &CancellationToken) -> Result<Arc<Vec<u8>>, DecodeError> {
+        let mut picture = self.picture.lock().unwrap();
+        if let Some(picture) = picture.as_ref() {
+            return Ok(picture.clone());
+        }
+
+        let mut session = DecodeSession::open(&self.path, 0, 0, &self.output, false)?;
+        let (_, rgba) = session
+            .read_range(0, 0, cancel)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| DecodeError::NoVideoStream {
+                path: self.path.clone(),
+            })?;
+        Ok(picture.insert(Arc::new(rgba)).clone())
+    }
+}
+
+impl FrameSource for StillImageSource {
+    fn metadata(&self) -> SourceMetadata {
+        SourceMetadata {
+            frame_count: None,
+            fps: None,
+            still: true,
+            width: self.output.width,
+            height: self.output.height,
+        }
+    }
+
+    fn seek(&self, _seconds: f64) -> Result<u32, DecodeError> {
+        Ok(0)
+    }
+
+    fn frame_time(&self, _frame: u32) -> Option<f64> {
+        None
+    }
+
+    fn decode_range(
+        &self,
+        start: u32,
+        end: u32,
+        cancel: &CancellationToken,
+    ) -> Result<Vec<(u32, Vec<u8>)>, DecodeError> {
+        let picture = self.picture(cancel)?;
+        Ok((start..=end)
+            .map(|frame| (frame, picture.as_ref().clone()))
+            .collect())
+    }
+
+    fn filter_graph(&self) -> String {
+        let stream = media_index(&self.path, 0)
+            .map(|index| index.stream())
+            .unwrap_or_default();
+        self.output.filters(stream).join(",")
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        vec![PathBuf::from(&self.path)]
+    }
+
+    fn close(&self) {
+        self.picture.lock().unwrap().take();
+    }
+}
@@ -237,8 +237,17 @@ pub async fn concat_segments_mp4(
 #[derive(Debug, Clone, Deserialize)]
 #[serde(tag = "kind", rename_all = "lowercase")]
 pub enum AudioSourceResolved {
-    Video { path: String },
-    Sound { path: String },
+    /// `stream` is the position among the file's audio streams (`a:N`).
+    Video {
+        path: String,
+        #[serde(default)]
+        stream: u32,
+    },
+    Sound {
+        path: String,
+        #[serde(default)]
+        stream: u32,
+    },
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -249,8 +258,36 @@ pub struct AudioSegmentResolved {
     pub project_start_frame: i64,
     #[serde(rename = "sourceStartFrame")]
     pub source_start_frame: i64,
+    /// Exact source start of a remapped piece. Used instead of `source_start_frame` when set.
+    #[serde(rename = "sourceStartSeconds", default)]
+    pub source_start_seconds: Option<f64>,
     #[serde(rename = "durationFrames")]
     pub duration_frames: i64,
+    /// Playback speed of a remapped clip. Negative plays the source backwards.
+    #[serde(default = "default_speed")]
+    pub speed: f64,
+}
+
+fn default_speed() -> f64 {
+    1.0
+}
+
+/// `atempo` filters for `speed`, chained because one instance only goes down to 0.5x.
+fn atempo_chain(speed: f64) -> String {
+    let mut filters = Vec::new();
+    let mut remaining = speed;
+    while remaining < 0.5 {
+        filters.push("atempo=0.5".to_string());
+        remaining /= 0.5;
+    }
+    while remaining > 100.0 {
+        filters.push("atempo=100".to_string());
+        remaining /= 100.0;
+    }
+    if (remaining - 1.0).abs() > 1e-6 {
+        filters.push(format!("atempo={remaining:.6}"));
+    }
+    filters.join(",")
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -279,8 +316,8 @@ pub async fn mux_audio_plan_into_mp4(
     let mut next_input_index: usize = 1; // input #0 is video
     for seg in &plan.segments {
         let path = match &seg.source {
-            AudioSourceResolved::Video { path } => path,
-            AudioSourceResolved::Sound { path } => path,
+            AudioSourceResolved::Video { path, .. } => path,
+            AudioSourceResolved::Sound { path, .. } => path,
         };
         if !sources.contains_key(path) {
             sources.insert(path.clone(), next_input_index);
@@ -317,9 +354,9 @@ pub async fn mux_audio_plan_into_mp4(
 
     for seg in plan.segments.iter() {
         let n = segment_labels.len();
-        let src_path = match &seg.source {
-            AudioSourceResolved::Video { path } => path,
-            AudioSourceResolved::Sound { path } => path,
+        let (src_path, stream) = match &seg.source {
+            AudioSourceResolved::Video { path, stream } => (path, stream),
+            AudioSourceResolved::Sound { path, stream } => (path, stream),
         };
         let Some(&input_idx) = ordered_sources
             .iter()
@@ -336,12 +373,31 @@ pub async fn mux_audio_plan_into_mp4(
             continue;
         }
 
-        let start_sec = source_start_frame / fps;
-        let dur_sec = duration_frames / fps;
+        let speed = if seg.speed.is_finite() { seg.speed } else { 1.0 };
+        if speed == 0.0 {
+            continue;
+        }
+
+        let start_sec = seg
+            .source_start_seconds
+            .filter(|seconds| seconds.is_finite())
+            .map(|seconds| seconds.max(0.0))
+            .unwrap_or(source_start_frame / fps);
+        let dur_sec = duration_frames / fps * speed.abs();
         let delay_ms = ((project_start_frame / fps) * 1000.0).round().max(0.0) as i64;
 
+        let mut remap = String::new();
+        if speed < 0.0 {
+            remap.push_str("areverse,");
+        }
+        let tempo = atempo_chain(speed.abs());
+        if !tempo.is_empty() {
+            remap.push_str(&tempo);
+            remap.push(',');
+        }
+
         filter_parts.push(format!(
-            "[{input_idx}:a]atrim=start={}:duration={},asetpts=PTS-STARTPTS,aresample=48000,adelay={delay_ms}:all=1[a{n}]",
+            "[{input_idx}:a:{stream}]atrim=start={}:duration={},asetpts=PTS-STARTPTS,{remap}aresample=48000,adelay={delay_ms}:all=1[a{n}]",
             fmt_f(start_sec),
             fmt_f(dur_sec),
         ));
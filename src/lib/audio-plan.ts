@@ -1,4 +1,26 @@
 import { useSyncExternalStore } from "react"
+import type { TimeRemap } from "./video/video"
+
+/**
+ * Picks one stream of a multi-track file: by position among the streams of its kind,
+ * by language tag, or by part of the title. Omitted means the first stream.
+ *
+ * 複数トラックのファイルからストリームを選びます。種類ごとの番号、言語タグ、タイトルの一部で指定し、
+ * 省略すると最初のストリームになります。
+ *
+ * @example
+ * ```ts
+ * const mic: StreamSelector = { title: "mic" }
+ * ```
+ */
+export type StreamSelector = {
+  index?: number
+  language?: string
+  title?: string
+}
+
+const sameStream = (a?: StreamSelector, b?: StreamSelector) =>
+  (a?.index ?? 0) === (b?.index ?? 0) && a?.language === b?.language && a?.title === b?.title
 
 /**
  * Audio source reference used for timeline audio segments.
@@ -11,13 +33,14 @@ import { useSyncExternalStore } from "react"
  * ```
  */
 export type AudioSourceRef =
-  | { kind: "video"; path: string }
-  | { kind: "sound"; path: string } // reserved for future <Sound />
+  | { kind: "video"; path: string; stream?: StreamSelector }
+  | { kind: "sound"; path: string; stream?: StreamSelector } // reserved for future <Sound />
 
 /**
- * Audio segment mapped onto the project timeline.
+ * Audio segment mapped onto the project timeline. With `remap`, the audio follows the same
+ * time remapping as its video and `sourceStartFrame` is not used.
  *
- * プロジェクトタイムライン上の音声セグメント。
+ * プロジェクトタイムライン上の音声セグメント。`remap` があると映像と同じタイムリマップに従い、`sourceStartFrame` は使いません。
  *
  * @example
  * ```ts
@@ -36,6 +59,7 @@ export type AudioSegment = {
   projectStartFrame: number
   sourceStartFrame: number
   durationFrames: number
+  remap?: TimeRemap
 }
 
 type Listener = () => void
@@ -71,9 +95,11 @@ export const registerAudioSegmentGlobal = (segment: AudioSegment) => {
     existing.source.kind === segment.source.kind &&
     ("path" in existing.source ? existing.source.path : "") ===
       ("path" in segment.source ? segment.source.path : "") &&
+    sameStream(existing.source.stream, segment.source.stream) &&
     existing.projectStartFrame === segment.projectStartFrame &&
     existing.sourceStartFrame === segment.sourceStartFrame &&
-    existing.durationFrames === segment.durationFrames
+    existing.durationFrames === segment.durationFrames &&
+    JSON.stringify(existing.remap) === JSON.stringify(segment.remap)
   ) {
     return
   }
@@ -4,7 +4,7 @@ import { PROJECT_SETTINGS } from "../../../project/project";
 import { useCurrentFrame } from "../frame";
 import { useClipActive, useClipStart, useProvideClipDuration } from "../clip";
 import { createManualPromise, type ManualPromise } from "../../util/promise";
-import { normalizeVideo, video_fps, video_length, type Video, type VideoResolvedTrimProps } from "./video";
+import { normalizeVideo, video_length, type Video, type VideoFit, type VideoResolvedTrimProps } from "./video";
 
 // Track pending frame draws so headless callers can await completion.
 const pendingFramePromises = new Set<Promise<void>>();
@@ -14,6 +14,135 @@ const trackPending = (manual: ManualPromise<void>) => {
   manual.promise.finally(() => pendingFramePromises.delete(manual.promise));
 };
 
+// How far ahead of the requested frame the backend is asked to decode.
+const PREFETCH_AHEAD_SECONDS = 2;
+
+const FRAME_MAGIC = 0x52465346; // "FSFR" (little endian)
+const FRAME_VERSION = 1;
+const PIXEL_FORMAT_RGBA8 = 1;
+
+/**
+ * Flags carried in the frame packet header.
+ *
+ * フレームパケットヘッダのフラグ。
+ */
+export const FrameFlags = {
+  substitutedPrevious: 1 << 0,
+  placeholder: 1 << 1,
+  decodeError: 1 << 2,
+  blended: 1 << 3,
+} as const;
+
+/**
+ * Decoded /ws frame packet.
+ *
+ * /ws のフレームパケット。
+ */
+export type FramePacket = {
+  width: number
+  height: number
+  stride: number
+  flags: number
+  frameIndex: number
+  sourceFrame: number
+  timestampUs: bigint
+  rgba: Uint8ClampedArray
+}
+
+/**
+ * Parses a binary frame packet sent by the backend. Returns null for unknown layouts.
+ *
+ * バックエンドから送られたフレームパケットを解析します。未知の形式なら null を返します。
+ *
+ * @example
+ * ```ts
+ * const packet = parseFramePacket(event.data as ArrayBuffer)
+ * ```
+ */
+export const parseFramePacket = (buffer: ArrayBuffer): FramePacket | null => {
+  if (buffer.byteLength < 8) return null;
+  const view = new DataView(buffer);
+  if (view.getUint32(0, true) !== FRAME_MAGIC) return null;
+  if (view.getUint16(4, true) !== FRAME_VERSION) return null;
+  const headerLength = view.getUint16(6, true);
+  if (buffer.byteLength < headerLength) return null;
+  if (view.getUint8(20) !== PIXEL_FORMAT_RGBA8) return null;
+
+  const payloadLength = view.getUint32(44, true);
+  if (headerLength + payloadLength > buffer.byteLength) return null;
+
+  return {
+    width: view.getUint32(8, true),
+    height: view.getUint32(12, true),
+    stride: view.getUint32(16, true),
+    flags: view.getUint32(24, true),
+    frameIndex: view.getUint32(28, true),
+    sourceFrame: view.getUint32(32, true),
+    timestampUs: view.getBigInt64(36, true),
+    rgba: new Uint8ClampedArray(buffer, headerLength, payloadLength),
+  };
+}
+
+/**
+ * Typed decode error sent by the backend as a JSON text message.
+ *
+ * バックエンドから JSON テキストで送られるデコードエラー。
+ */
+export type FrameErrorMessage = {
+  type: "error"
+  video: string
+  frame: number
+  message: string
+  error:
+    | { code: "invalid_path"; path: string; reason: string }
+    | { code: "file_not_found"; path: string }
+    | { code: "no_video_stream"; path: string }
+    | { code: "frame_out_of_range"; path: string; frame: number; frame_count: number }
+    | { code: "ffmpeg_unavailable"; reason: string }
+    | { code: "ffmpeg"; reason: string; status: number | null; stderr: string }
+    | { code: "cancelled" }
+}
+
+const parseErrorMessage = (text: string): FrameErrorMessage | null => {
+  try {
+    const payload = JSON.parse(text)
+    if (payload && payload.type === "error") return payload as FrameErrorMessage
+  } catch {
+    // ignore
+  }
+  return null
+}
+
+const drawErrorOverlay = (
+  canvas: HTMLCanvasElement,
+  ctx: CanvasRenderingContext2D,
+  message: string,
+) => {
+  ctx.fillStyle = "rgba(0, 0, 0, 0.85)";
+  ctx.fillRect(0, 0, canvas.width, canvas.height);
+  ctx.fillStyle = "#ff5555";
+  ctx.font = `${Math.max(12, Math.round(canvas.height / 30))}px sans-serif`;
+  ctx.textAlign = "center";
+  ctx.textBaseline = "middle";
+  ctx.fillText(message, canvas.width / 2, canvas.height / 2, canvas.width * 0.9);
+}
+
+const reportBadFrame = (path: string, packet: FramePacket) => {
+  if (packet.flags & FrameFlags.placeholder) {
+    console.warn(`video: placeholder frame at ${packet.frameIndex} (${path})`);
+  } else if (packet.flags & FrameFlags.substitutedPrevious) {
+    console.warn(`video: frame ${packet.frameIndex} substituted by ${packet.sourceFrame} (${path})`);
+  }
+}
+
+/**
+ * Which decode queue a frame request joins on the backend. Omitted requests are decoded as
+ * `"interactive"`.
+ *
+ * フレーム要求がバックエンドで入るデコードの優先度。省略すると `"interactive"` になります。
+ */
+export type DecodePriority = "interactive" | "render" | "prefetch"
+
 /**
  * Props for VideoCanvasRender (render-mode video canvas).
  *
@@ -27,6 +156,8 @@ const trackPending = (manual: ManualPromise<void>) => {
 export type VideoCanvasRenderProps = {
   video: Video | string
   style?: CSSProperties
+  fit?: VideoFit
+  priority?: DecodePriority
 } & VideoResolvedTrimProps
 
 /**
@@ -39,7 +170,7 @@ export type VideoCanvasRenderProps = {
  * <VideoCanvasRender video="assets/demo.mp4" trimStartFrames={30} trimEndFrames={0} />
  * ```
  */
-export const VideoCanvasRender = ({ video, style, trimStartFrames = 0, trimEndFrames = 0 }: VideoCanvasRenderProps) => {
+export const VideoCanvasRender = ({ video, style, fit, priority, trimStartFrames = 0, trimEndFrames = 0 }: VideoCanvasRenderProps) => {
   const canvasRef = useRef<HTMLCanvasElement | null>(null);
   const wsRef = useRef<WebSocket | null>(null);
   const pendingMapRef = useRef<Map<number, { manual: ManualPromise<void>; projectFrame: number }>>(new Map());
@@ -47,8 +178,9 @@ export const VideoCanvasRender = ({ video, style, trimStartFrames = 0, trimEndFr
   const lastDrawnFrameRef = useRef<number | null>(null);
   const requestedFrameRef = useRef<number | null>(null);
   const reconnectTimerRef = useRef<number | null>(null);
+  const lastErrorRef = useRef<FrameErrorMessage | null>(null);
+  const prefetchedRangeRef = useRef<{ start: number; end: number } | null>(null);
   const resolved = useMemo(() => normalizeVideo(video), [video]);
-  const fps = useMemo(() => video_fps(resolved), [resolved]);
   const rawDurationFrames = useMemo(() => video_length(resolved), [resolved]);
   const durationFrames = Math.max(0, rawDurationFrames - trimStartFrames - trimEndFrames);
   useProvideClipDuration(durationFrames);
@@ -126,15 +258,15 @@ export const VideoCanvasRender = ({ video, style, trimStartFrames = 0, trimEndFr
         return;
       }
 
-      const playbackFrameRaw =
-        fps > 0
-          ? Math.round(((clampedFrame + sourceStart) * fps) / PROJECT_SETTINGS.fps)
-          : clampedFrame + sourceStart;
-      const playbackFrame = Math.min(Math.max(playbackFrameRaw, sourceStart), sourceEnd);
+      // プロジェクトのフレームで要求し、ソースのフレームへの対応はバックエンドに任せる
+      const projectSourceFrame = Math.min(clampedFrame + sourceStart, sourceEnd);
+
+      // リマップ時はソースのフレームが時刻から決まるので、クリップ内のフレームで要求を識別する
+      const requestFrame = resolved.remap ? clampedFrame : projectSourceFrame;
 
       const alreadyDrawn =
         lastDrawnFrameRef.current != null && lastDrawnFrameRef.current >= clampedFrame;
-      const hasPendingSamePlayback = pendingMapRef.current.has(playbackFrame);
+      const hasPendingSamePlayback = pendingMapRef.current.has(requestFrame);
       if (alreadyDrawn && !hasPendingSamePlayback) {
         return;
       }
@@ -142,22 +274,83 @@ export const VideoCanvasRender = ({ video, style, trimStartFrames = 0, trimEndFr
       const manual = createManualPromise();
       trackPending(manual);
 
-      const existing = pendingMapRef.current.get(playbackFrame);
+      const existing = pendingMapRef.current.get(requestFrame);
       if (existing) {
         existing.manual.reject(new Error("superseded by newer request"));
       }
-      pendingMapRef.current.set(playbackFrame, { manual, projectFrame: clampedFrame });
+      pendingMapRef.current.set(requestFrame, { manual, projectFrame: clampedFrame });
 
+      // Ask by project frame and rate; the backend conforms it to the source frame rate,
+      // which also picks the right picture of variable-frame-rate sources.
+      // `frame` is echoed back by the backend and keys the pending request.
+      // With a remap, the frame into the clip is sent and the backend maps it to the source.
       const req = {
         video: resolved.path,
         width: PROJECT_SETTINGS.width,
         height: PROJECT_SETTINGS.height,
-        frame: playbackFrame,
+        frame: requestFrame,
+        project_fps: PROJECT_SETTINGS.fps,
+        ...(priority ? { priority } : {}),
+        ...(fit ? { scale: { mode: fit } } : {}),
+        ...(resolved.videoStream ? { stream: resolved.videoStream } : {}),
+        ...(resolved.deinterlace ? { deinterlace: resolved.deinterlace } : {}),
+        ...(resolved.grade ? { grade: resolved.grade } : {}),
+        ...(resolved.key ? { key: resolved.key } : {}),
+        ...(resolved.remap ? { remap: resolved.remap } : {}),
+        ...(resolved.conform ? { conform: resolved.conform } : {}),
       };
 
       ws.send(JSON.stringify(req));
+
+      // Keep a window of upcoming frames warm on the backend, in project frames so only the
+      // source frames they show are cached, with the same conform mode as the frame requests.
+      // Remapped clips don't play source frames in order, so they are not prefetched.
+      const aheadFrames = Math.max(1, Math.ceil(PROJECT_SETTINGS.fps * PREFETCH_AHEAD_SECONDS));
+      const prefetched = prefetchedRangeRef.current;
+      if (
+        !resolved.remap &&
+        (!prefetched ||
+          projectSourceFrame < prefetched.start ||
+          projectSourceFrame + aheadFrames / 2 > prefetched.end)
+      ) {
+        const start = projectSourceFrame + 1;
+        const end = Math.min(sourceEnd, projectSourceFrame + aheadFrames);
+        if (start <= end) {
+          ws.send(JSON.stringify({
+            type: "prefetch",
+            video: resolved.path,
+            width: PROJECT_SETTINGS.width,
+            height: PROJECT_SETTINGS.height,
+            start,
+            end,
+            project_fps: PROJECT_SETTINGS.fps,
+            priority: 0,
+            ...(fit ? { scale: { mode: fit } } : {}),
+            ...(resolved.videoStream ? { stream: resolved.videoStream } : {}),
+            ...(resolved.deinterlace ? { deinterlace: resolved.deinterlace } : {}),
+            ...(resolved.grade ? { grade: resolved.grade } : {}),
+            ...(resolved.key ? { key: resolved.key } : {}),
+            ...(resolved.conform ? { conform: resolved.conform } : {}),
+          }));
+          prefetchedRangeRef.current = { start: projectSourceFrame, end };
+        }
+      }
     },
-    [durationFrames, fps, resolved.path, trimEndFrames, trimStartFrames, rawDurationFrames],
+    [
+      durationFrames,
+      fit,
+      priority,
+      resolved.path,
+      resolved.videoStream,
+      resolved.deinterlace,
+      resolved.grade,
+      resolved.key,
+      resolved.remap,
+      resolved.conform,
+      trimEndFrames,
+      trimStartFrames,
+      rawDurationFrames,
+    ],
   );
 
   useEffect(() => {
@@ -200,37 +393,45 @@ export const VideoCanvasRender = ({ video, style, trimStartFrames = 0, trimEndFr
       };
 
       socket.onmessage = (event) => {
+        if (typeof event.data === "string") {
+          const errorMessage = parseErrorMessage(event.data);
+          if (errorMessage) {
+            lastErrorRef.current = errorMessage;
+            console.error(`video: ${errorMessage.message}`, errorMessage.error);
+          }
+          return;
+        }
         if (!(event.data instanceof ArrayBuffer)) return;
-        const buffer = event.data as ArrayBuffer;
-        const view = new DataView(buffer);
-        const width = view.getUint32(0, true);
-        const height = view.getUint32(4, true);
-        const frameIndex = view.getUint32(8, true);
-        const rgba = new Uint8ClampedArray(buffer, 12);
-
-        if (width * height * 4 !== rgba.length) {
-          rejectPendingRequests(new Error("frame size mismatch"));
+        const packet = parseFramePacket(event.data as ArrayBuffer);
+        if (!packet) {
+          rejectPendingRequests(new Error("invalid frame packet"));
           return;
         }
-
-        if (canvas.width !== width || canvas.height !== height) {
-          canvas.width = width;
-          canvas.height = height;
+        const { width, height, frameIndex, rgba } = packet;
+
+        if (packet.flags & FrameFlags.decodeError) {
+          // The typed error arrives as a text message right before this packet.
+          const errorMessage = lastErrorRef.current;
+          drawErrorOverlay(canvas, ctx, errorMessage?.message ?? "decode error");
+        } else {
+          if (width * height * 4 !== rgba.length) {
+            rejectPendingRequests(new Error("frame size mismatch"));
+            return;
+          }
+
+          reportBadFrame(resolved.path, packet);
+
+          if (canvas.width !== width || canvas.height !== height) {
+            canvas.width = width;
+            canvas.height = height;
+          }
+
+          const imageData = new ImageData(rgba, width, height);
+          ctx.putImageData(imageData, 0, 0);
         }
 
-        const imageData = new ImageData(rgba, width, height);
-        ctx.putImageData(imageData, 0, 0);
-
         const pending = pendingMapRef.current.get(frameIndex);
-        const projectFrame =
-          pending?.projectFrame ??
-          Math.max(
-            0,
-            Math.round(
-              ((frameIndex - trimStartFrames) * PROJECT_SETTINGS.fps) /
-              Math.max(1, fps || PROJECT_SETTINGS.fps),
-            ),
-          );
+        const projectFrame = pending?.projectFrame ?? Math.max(0, frameIndex - trimStartFrames);
 
         if (pending) {
           pendingMapRef.current.delete(frameIndex);
@@ -5,24 +5,159 @@ import { PROJECT_SETTINGS } from "../../../project/project";
 import { useIsPlaying, useIsRender } from "../studio-state";
 import { useClipActive, useClipRange, useClipStart, useProvideClipDuration } from "../clip";
 import { registerAudioSegmentGlobal, unregisterAudioSegmentGlobal } from "../audio-plan";
+import type { StreamSelector } from "../audio-plan";
 import { VideoCanvasRender } from "./video-render";
 import type { Trim } from "../trim";
 import { resolveTrimFrames } from "../trim";
 
 /**
- * Video source descriptor.
+ * Video source descriptor. `videoStream` / `audioStream` pick tracks of multi-track files
+ * (the first ones by default). `deinterlace` picks how interlaced footage is decoded, `grade`
+ * color-corrects it, `key` keys out a green screen, `remap` changes its speed and `conform` picks how
+ * a source at another frame rate lands on project frames (with any of the last four, the studio
+ * previews backend frames instead of <video>).
  *
- * 動画ソースの記述。
+ * 動画ソースの記述。`videoStream` / `audioStream` で複数トラックのファイルのトラックを選びます (省略時は最初のもの)。
+ * `deinterlace` でインターレース素材のデコード方法を、`grade` で色補正を、`key` でクロマキーを、`remap` で速度の変更を、
+ * `conform` でフレームレートの違う素材をプロジェクトのフレームに合わせる方法を指定します
+ * (`grade` / `key` / `remap` / `conform` があるとスタジオでも <video> ではなくバックエンドのフレームを表示します)。
  *
  * @example
  * ```ts
  * const video: Video = { path: "assets/demo.mp4" }
+ * const mic: Video = { path: "assets/screen.mkv", audioStream: { title: "mic" } }
+ * const film: Video = { path: "assets/film-24fps.mp4", conform: "blend" }
  * ```
  */
 export type Video = {
   path: string
+  videoStream?: StreamSelector
+  audioStream?: StreamSelector
+  deinterlace?: Deinterlace
+  grade?: ColorGrade
+  key?: ChromaKey
+  remap?: TimeRemap
+  conform?: FrameRateConform
 }
 
+/**
+ * How a source whose frame rate differs from the project's is shown: the nearest source frame
+ * ("nearest", default), a mix of the two around each project frame ("blend") or
+ * motion-interpolated frames when the project rate is higher ("interpolate"). With "nearest" at a
+ * lower project rate, frames that are never shown are dropped right after decoding, before
+ * color conversion and scaling.
+ *
+ * フレームレートがプロジェクトと異なる素材の表示方法です。最も近いソースのフレーム ("nearest"、既定)、
+ * 前後2フレームの合成 ("blend")、プロジェクトの方が高いときの動き補間 ("interpolate") から選びます。
+ * "nearest" でプロジェクトの方が低いフレームレートのときは、表示されないフレームをデコード直後に捨て、色変換や縮小をしません。
+ *
+ * @example
+ * ```ts
+ * const conform: FrameRateConform = "interpolate"
+ * ```
+ */
+export type FrameRateConform = "nearest" | "blend" | "interpolate"
+
+/**
+ * Time remapping, from seconds into the clip to seconds in the source. "speed" plays from
+ * `offset` at `speed` (negative plays backwards), "reverse" plays backwards from `from`,
+ * "freeze" holds the frame at `time`, and "curve" integrates linearly interpolated speed
+ * keyframes from `offset`. `blend` shows in-between times as the nearest frame ("none", default),
+ * a mix of two frames ("frames") or motion-interpolated frames ("interpolate"). The clip's audio
+ * follows the same remap. `trim` then only limits the clip's length.
+ *
+ * タイムリマップで、クリップ内の秒数をソースの秒数に対応付けます。"speed" は `offset` から `speed` 倍速
+ * (負なら逆再生)、"reverse" は `from` から逆再生、"freeze" は `time` のフレームで静止、"curve" は線形補間した速度の
+ * キーフレームを `offset` から積分します。`blend` は中間の時刻を最も近いフレーム ("none"、既定)、2フレームの合成
+ * ("frames")、動き補間 ("interpolate") のどれで表示するかです。音声も同じリマップに従います。`trim` はクリップの長さだけに効きます。
+ *
+ * @example
+ * ```ts
+ * const slowMo: Video = { path: "assets/jump.mp4", remap: { mode: "speed", speed: 0.25, offset: 3, blend: "interpolate" } }
+ * const ramp: Video = {
+ *   path: "assets/run.mp4",
+ *   remap: { mode: "curve", keyframes: [{ time: 0, speed: 1 }, { time: 1, speed: 0.2 }, { time: 3, speed: 1 }] },
+ * }
+ * ```
+ */
+export type TimeRemap = (
+  | { mode: "speed"; speed: number; offset?: number }
+  | { mode: "reverse"; from: number; speed?: number }
+  | { mode: "freeze"; time: number }
+  | { mode: "curve"; keyframes: { time: number; speed: number }[]; offset?: number }
+) & {
+  blend?: "none" | "frames" | "interpolate"
+}
+
+/**
+ * Chroma key applied by the backend, producing frames with alpha. `similarity` (default 0.1),
+ * `blend` (edge softness) and `spill` (removal of the key color's cast) range from 0 to 1.
+ * "chroma" (default) compares chroma only; "color" compares RGB.
+ *
+ * バックエンドで掛けるクロマキーで、アルファ付きのフレームになります。`similarity` (既定 0.1)、
+ * `blend` (境界のぼかし)、`spill` (キー色のかぶり除去) は 0〜1 です。"chroma" (既定) は色差のみ、"color" は RGB で比較します。
+ *
+ * @example
+ * ```ts
+ * const video: Video = { path: "assets/greenscreen.mp4", key: { color: "#00ff00", similarity: 0.15, spill: 0.8 } }
+ * ```
+ */
+export type ChromaKey = {
+  color: string
+  similarity?: number
+  blend?: number
+  spill?: number
+  method?: "chroma" | "color"
+}
+
+/**
+ * Color correction applied by the backend while decoding, identically in studio and render.
+ * `lut` is a `.cube` file path (project-relative like `path`); `exposure` is in stops (default 0),
+ * `contrast` and `saturation` are factors (default 1).
+ *
+ * デコード時にバックエンドで掛ける色補正で、スタジオとレンダーで同じ結果になります。
+ * `lut` は `.cube` ファイルのパス (`path` と同じくプロジェクト相対)、`exposure` は段数 (既定 0)、
+ * `contrast` と `saturation` は倍率 (既定 1) です。
+ *
+ * @example
+ * ```ts
+ * const video: Video = { path: "assets/slog3.mp4", grade: { lut: "luts/slog3-to-709.cube", saturation: 1.1 } }
+ * ```
+ */
+export type ColorGrade = {
+  lut?: string
+  exposure?: number
+  contrast?: number
+  saturation?: number
+}
+
+/**
+ * Deinterlacing mode. "auto" (default) deinterlaces frames flagged interlaced, "bwdif" / "yadif"
+ * deinterlace every frame, "double" outputs one frame per field at twice the frame rate.
+ *
+ * インターレース解除の方法。"auto" (既定) はインターレースのフレームだけ、"bwdif" / "yadif" は全フレーム、
+ * "double" はフィールドごとに1フレーム (2倍のフレームレート) にします。
+ *
+ * @example
+ * ```ts
+ * const video: Video = { path: "assets/broadcast.ts", deinterlace: "double" }
+ * ```
+ */
+export type Deinterlace = "off" | "auto" | "bwdif" | "yadif" | "double"
+
+/**
+ * How the video picture fits the element box.
+ * "stretch" ignores the aspect ratio, "contain" letterboxes, "cover" crops.
+ *
+ * 動画の収め方。"stretch" は縦横比を無視、"contain" は余白付き、"cover" ははみ出しを切り取ります。
+ *
+ * @example
+ * ```tsx
+ * <Video video="assets/portrait.mp4" fit="contain" />
+ * ```
+ */
+export type VideoFit = "stretch" | "contain" | "cover"
+
 /**
  * Props for <Video>.
  *
@@ -37,6 +172,7 @@ export type VideoProps = {
   video: Video | string
   style?: CSSProperties
   trim?: Trim
+  fit?: VideoFit
 }
 
 /**
@@ -54,6 +190,23 @@ export const normalizeVideo = (video: Video | string): Video => {
   return video
 }
 
+const IMAGE_EXTENSIONS = ["png", "apng", "jpg", "jpeg", "webp", "avif", "gif", "bmp", "tif", "tiff"]
+
+/**
+ * Whether the path is a still or animated image (decoded by the backend, not <video>).
+ *
+ * 静止画・アニメーション画像かどうか (<video> ではなくバックエンドでデコードします)。
+ *
+ * @example
+ * ```ts
+ * isImagePath("assets/loop.gif") // true
+ * ```
+ */
+export const isImagePath = (path: string): boolean => {
+  const extension = path.split(/[?#]/)[0].split(".").pop()?.toLowerCase() ?? ""
+  return IMAGE_EXTENSIONS.includes(extension)
+}
+
 const buildVideoUrl = (video: Video) => {
   const url = new URL("http://localhost:3000/video");
   url.searchParams.set("path", video.path);
@@ -63,6 +216,10 @@ const buildVideoUrl = (video: Video) => {
 const buildMetaUrl = (video: Video) => {
   const url = new URL("http://localhost:3000/video/meta");
   url.searchParams.set("path", video.path);
+  const stream = video.videoStream
+  if (stream?.index !== undefined) url.searchParams.set("stream", String(stream.index));
+  if (stream?.language) url.searchParams.set("language", stream.language);
+  if (stream?.title) url.searchParams.set("title", stream.title);
   return url.toString();
 }
 
@@ -170,11 +327,12 @@ export type VideoResolvedTrimProps = {
  * <Video video="assets/demo.mp4" trim={{ from: 30, duration: 120 }} />
  * ```
  */
-export const Video = ({ video, style, trim }: VideoProps) => {
+export const Video = ({ video, style, trim, fit }: VideoProps) => {
   const isRender = useIsRender()
   const id = useId()
   const clipRange = useClipRange()
   const resolvedVideo = useMemo(() => normalizeVideo(video), [video])
+  const isImage = useMemo(() => isImagePath(resolvedVideo.path), [resolvedVideo.path])
   const rawDurationFrames = useMemo(() => video_length(resolvedVideo), [resolvedVideo])
   const [durationOverrideFrames, setDurationOverrideFrames] = useState<number | null>(null)
   useEffect(() => {
@@ -202,7 +360,8 @@ export const Video = ({ video, style, trim }: VideoProps) => {
   )
 
   useEffect(() => {
-    if (!clipRange) return
+    // 画像には音声が無い
+    if (!clipRange || isImage) return
 
     const projectStartFrame = clipRange.start
     const clipDurationFrames = Math.max(0, clipRange.end - clipRange.start + 1)
@@ -212,22 +371,27 @@ export const Video = ({ video, style, trim }: VideoProps) => {
 
     registerAudioSegmentGlobal({
       id,
-      source: { kind: "video", path: resolvedVideo.path },
+      source: { kind: "video", path: resolvedVideo.path, stream: resolvedVideo.audioStream },
       projectStartFrame,
       sourceStartFrame: trimStartFrames,
       durationFrames,
+      ...(resolvedVideo.remap ? { remap: resolvedVideo.remap } : {}),
     })
 
     return () => {
       unregisterAudioSegmentGlobal(id)
     }
-  }, [clipRange, effectiveDurationFrames, id, resolvedVideo.path, trimEndFrames, trimStartFrames])
+  }, [clipRange, effectiveDurationFrames, id, isImage, resolvedVideo.path, resolvedVideo.audioStream, resolvedVideo.remap, trimEndFrames, trimStartFrames])
 
-  if (isRender) {
+  // アニメーション画像はブラウザの時計で進むので、スタジオでもバックエンドのフレームを描く
+  // グレーディング・クロマキー・タイムリマップは FFmpeg で掛けるので、スタジオでもレンダーと同じフレームを使う
+  if (isRender || isImage || resolvedVideo.grade || resolvedVideo.key || resolvedVideo.remap || resolvedVideo.conform) {
     return (
       <VideoCanvasRender
         video={video}
         style={style}
+        fit={fit}
+        priority={isRender ? "render" : undefined}
         trimStartFrames={trimStartFrames}
         trimEndFrames={trimEndFrames}
       />
@@ -237,6 +401,7 @@ export const Video = ({ video, style, trim }: VideoProps) => {
       <VideoCanvas
         video={video}
         style={style}
+        fit={fit}
         trimStartFrames={trimStartFrames}
         trimEndFrames={trimEndFrames}
         durationOverrideFrames={durationOverrideFrames}
@@ -255,6 +420,7 @@ type VideoCanvasProps = Omit<VideoProps, "trim"> &
 const VideoCanvas = ({
   video,
   style,
+  fit,
   trimStartFrames = 0,
   trimEndFrames = 0,
   durationOverrideFrames = null,
@@ -311,6 +477,7 @@ const VideoCanvas = ({
     width: "100%",
     height: "100%",
     backgroundColor: "#000",
+    ...(fit ? { objectFit: fit === "stretch" ? "fill" : fit } : {}),
   }
 
   useEffect(() => {